@@ -0,0 +1,154 @@
+use correlation::average_ranks;
+use distribution::chi_square_upper_p;
+
+/// Kendall's coefficient of concordance together with its chi-squared test
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Concordance {
+    /// Kendall's W, from 0 (no agreement) to 1 (complete agreement)
+    pub w: f64,
+    pub chi_square: f64,
+    pub degrees_of_freedom: f64,
+    /// Probability of at least this much agreement if the rankings were independent
+    pub p_value: f64,
+}
+
+/// How many judges preferred each sample of a pair, and how many judged them equal
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PairVotes {
+    pub a: f64,
+    pub b: f64,
+    pub ties: f64,
+}
+
+/// Kendall's W over the scores that each judge (outer) gave to each sample (inner), correcting
+/// for tied scores.
+///
+/// Returns `None` with fewer than two judges or samples, or when every judge tied all samples.
+pub fn kendall_w(scores: &[Vec<f64>]) -> Option<Concordance> {
+    let m = scores.len();
+    if m < 2 {
+        return None;
+    }
+    let n = scores[0].len();
+    if n < 2 {
+        return None;
+    }
+
+    let mut rank_sums = vec![0.0; n];
+    let mut tie_correction = 0.0;
+    for judge_scores in scores {
+        assert_eq!(judge_scores.len(), n);
+        let ranks = average_ranks(judge_scores);
+        for (sum, rank) in rank_sums.iter_mut().zip(&ranks) {
+            *sum += *rank;
+        }
+        tie_correction += tie_sizes(&ranks)
+            .into_iter()
+            .map(|t| t * t * t - t)
+            .sum::<f64>();
+    }
+
+    let mean = rank_sums.iter().sum::<f64>() / n as f64;
+    let s: f64 = rank_sums.iter().map(|sum| (sum - mean) * (sum - mean)).sum();
+
+    let (m, n) = (m as f64, n as f64);
+    let denominator = m * m * (n * n * n - n) - m * tie_correction;
+    if denominator <= 0.0 {
+        return None;
+    }
+
+    let w = 12.0 * s / denominator;
+    let chi_square = m * (n - 1.0) * w;
+
+    Some(Concordance {
+        w: w,
+        chi_square: chi_square,
+        degrees_of_freedom: n - 1.0,
+        p_value: chi_square_upper_p(chi_square, n - 1.0),
+    })
+}
+
+/// Kendall's coefficient of agreement u for paired comparisons.
+///
+/// Generalized to incomplete designs by comparing the agreeing pairs of judges with the number
+/// of pairs of judges that compared each pair of samples. Two judges that tied a pair agree,
+/// while a tie and a preference count as half an agreement. It is 1 when all judges agree on
+/// all pairs. Returns `None` when no pair of samples was compared by at least two judges.
+pub fn kendall_u(votes: &[PairVotes]) -> Option<f64> {
+    fn pairs(count: f64) -> f64 {
+        count * (count - 1.0) / 2.0
+    }
+
+    let agreeing: f64 = votes
+        .iter()
+        .map(|v| pairs(v.a) + pairs(v.b) + pairs(v.ties) + 0.5 * v.ties * (v.a + v.b))
+        .sum();
+    let possible: f64 = votes.iter().map(|v| pairs(v.a + v.b + v.ties)).sum();
+
+    if possible > 0.0 {
+        Some(2.0 * agreeing / possible - 1.0)
+    } else {
+        None
+    }
+}
+
+/// Sizes of the groups of tied ranks
+fn tie_sizes(ranks: &[f64]) -> Vec<f64> {
+    let mut sorted = ranks.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut sizes = Vec::new();
+    let mut start = 0;
+    while start < sorted.len() {
+        let mut end = start + 1;
+        while end < sorted.len() && sorted[end] == sorted[start] {
+            end += 1;
+        }
+        sizes.push((end - start) as f64);
+        start = end;
+    }
+
+    sizes
+}
+
+#[test]
+fn test_kendall_w() {
+    let identical = vec![vec![1.0, 2.0, 3.0, 4.0]; 3];
+    assert!((kendall_w(&identical).unwrap().w - 1.0).abs() < 1.0e-9);
+
+    let scores = vec![
+        vec![1.0, 2.0, 3.0, 4.0],
+        vec![2.0, 1.0, 4.0, 3.0],
+        vec![1.0, 3.0, 2.0, 4.0],
+    ];
+    let concordance = kendall_w(&scores).unwrap();
+    assert!((concordance.w - 0.644_444_44).abs() < 1.0e-6);
+    assert!((concordance.chi_square - 5.8).abs() < 1.0e-6);
+
+    assert!(kendall_w(&[vec![1.0, 1.0], vec![2.0, 2.0]]).is_none());
+}
+
+#[test]
+fn test_kendall_u() {
+    let votes = |a: f64, b: f64, ties: f64| PairVotes {
+        a: a,
+        b: b,
+        ties: ties,
+    };
+
+    // Three judges that all agree on both pairs
+    let agree = [votes(3.0, 0.0, 0.0), votes(0.0, 3.0, 0.0)];
+    assert!((kendall_u(&agree).unwrap() - 1.0).abs() < 1.0e-9);
+
+    // Two judges that disagree
+    assert!((kendall_u(&[votes(1.0, 1.0, 0.0)]).unwrap() + 1.0).abs() < 1.0e-9);
+
+    // Two agreeing judges and one tie: 1 + 2 * 0.5 of 3 pairs of judges agree
+    let u = kendall_u(&[votes(2.0, 0.0, 1.0)]).unwrap();
+    assert!((u - 1.0 / 3.0).abs() < 1.0e-9);
+
+    // A single tie with a preference is half an agreement
+    assert!(kendall_u(&[votes(1.0, 0.0, 1.0)]).unwrap().abs() < 1.0e-9);
+
+    assert!(kendall_u(&[votes(1.0, 0.0, 0.0)]).is_none());
+}
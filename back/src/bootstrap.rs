@@ -0,0 +1,165 @@
+use rand::{Rng, SeedableRng, StdRng};
+
+/// What is resampled in each bootstrap replicate
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Resample {
+    /// Draw users with replacement (only meaningful for group rankings)
+    Users,
+    /// Draw each user's comparisons with replacement
+    Comparisons,
+}
+
+/// Confidence level used when none is given
+pub const DEFAULT_LEVEL: f32 = 0.95;
+
+/// Most iterations that can be run, since each one ranks all samples again
+pub const MAX_ITERATIONS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub iterations: usize,
+    /// Seed of the random number generator, making the replicates reproducible
+    pub seed: usize,
+    /// Confidence level of the intervals, such as 0.95
+    pub level: f32,
+    pub resample: Resample,
+}
+
+impl Options {
+    /// Check that the number of iterations is at most `MAX_ITERATIONS` and that the level is
+    /// between 0 and 1, describing the problem if not
+    pub fn validate(&self) -> Result<(), String> {
+        if self.iterations > MAX_ITERATIONS {
+            return Err(format!(
+                "Number of bootstrap iterations must be at most {}",
+                MAX_ITERATIONS
+            ));
+        }
+        if !(self.level > 0.0 && self.level < 1.0) {
+            return Err("Confidence level must be between 0 and 1".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// Bootstrap distribution of the weights of `num` samples
+pub struct Summary {
+    /// Lower and upper bound of the percentile confidence interval of each sample
+    pub intervals: Vec<(f32, f32)>,
+    /// Probability of each sample (outer) being at each rank (inner), where rank 0 is the best
+    pub rank_probabilities: Vec<Vec<f32>>,
+    /// Number of replicates that produced an estimate
+    pub replicates: usize,
+}
+
+/// Estimate weights once for each iteration, using a random number generator seeded from the
+/// options. Replicates where `estimate` returns `None` are left out.
+pub fn run<F>(options: &Options, mut estimate: F) -> Vec<Vec<f32>>
+where
+    F: FnMut(&mut StdRng) -> Option<Vec<f32>>,
+{
+    let mut rng: StdRng = SeedableRng::from_seed(&[options.seed][..]);
+    (0..options.iterations)
+        .filter_map(|_| estimate(&mut rng))
+        .collect()
+}
+
+/// Draw `len` indices in `0..len` with replacement
+pub fn resample<R: Rng>(rng: &mut R, len: usize) -> Vec<usize> {
+    (0..len).map(|_| rng.gen_range(0, len)).collect()
+}
+
+pub fn summarize(estimates: &[Vec<f32>], num: usize, level: f32) -> Summary {
+    let replicates = estimates.len();
+    if replicates == 0 {
+        return Summary {
+            intervals: Vec::new(),
+            rank_probabilities: Vec::new(),
+            replicates: 0,
+        };
+    }
+
+    let tail = (1.0 - level) / 2.0;
+    let intervals = (0..num)
+        .map(|sample| {
+            let mut weights: Vec<f32> = estimates.iter().map(|e| e[sample]).collect();
+            weights.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            (percentile(&weights, tail), percentile(&weights, 1.0 - tail))
+        })
+        .collect();
+
+    let mut rank_counts = vec![vec![0usize; num]; num];
+    for estimate in estimates {
+        let mut order: Vec<usize> = (0..num).collect();
+        order.sort_by(|&a, &b| estimate[b].partial_cmp(&estimate[a]).unwrap());
+        for (rank, &sample) in order.iter().enumerate() {
+            rank_counts[sample][rank] += 1;
+        }
+    }
+
+    let rank_probabilities = rank_counts
+        .into_iter()
+        .map(|counts| {
+            counts
+                .into_iter()
+                .map(|count| count as f32 / replicates as f32)
+                .collect()
+        })
+        .collect();
+
+    Summary {
+        intervals: intervals,
+        rank_probabilities: rank_probabilities,
+        replicates: replicates,
+    }
+}
+
+/// Nearest-rank percentile of sorted values, where `fraction` is in [0, 1]
+fn percentile(sorted: &[f32], fraction: f32) -> f32 {
+    let index = (fraction * (sorted.len() - 1) as f32).round() as usize;
+    sorted[index]
+}
+
+#[test]
+fn test_summarize() {
+    let estimates: Vec<Vec<f32>> = (0..101).map(|i| vec![i as f32, 50.5]).collect();
+    let summary = summarize(&estimates, 2, 0.9);
+
+    assert_eq!(summary.replicates, 101);
+    assert_eq!(summary.intervals[0], (5.0, 95.0));
+    assert_eq!(summary.intervals[1], (50.5, 50.5));
+    assert!((summary.rank_probabilities[0][0] - 50.0 / 101.0).abs() < 1.0e-6);
+    assert!((summary.rank_probabilities[1][0] - 51.0 / 101.0).abs() < 1.0e-6);
+}
+
+#[test]
+fn test_run_is_reproducible() {
+    let options = Options {
+        iterations: 10,
+        seed: 42,
+        level: 0.95,
+        resample: Resample::Users,
+    };
+    fn draw(rng: &mut StdRng) -> Option<Vec<f32>> {
+        Some(resample(rng, 5).into_iter().map(|i| i as f32).collect())
+    }
+
+    assert_eq!(run(&options, draw), run(&options, draw));
+}
+
+#[test]
+fn test_validate() {
+    let options = |iterations: usize, level: f32| Options {
+        iterations: iterations,
+        seed: 0,
+        level: level,
+        resample: Resample::Users,
+    };
+
+    assert!(options(MAX_ITERATIONS, 0.95).validate().is_ok());
+    assert!(options(MAX_ITERATIONS + 1, 0.95).validate().is_err());
+    assert!(options(100, 0.0).validate().is_err());
+    assert!(options(100, 1.5).validate().is_err());
+}
@@ -0,0 +1,211 @@
+use na::{DMatrix, DVector};
+
+use distribution::{normal_cdf, normal_pdf};
+
+/// Maximum number of Fisher scoring iterations
+const MAX_ITERATIONS: usize = 100;
+/// Largest change in any score between two iterations to consider the fit converged
+const TOLERANCE: f64 = 1.0e-9;
+/// Weak ridge penalty on the scores, keeping them finite when a sample always wins or loses
+const RIDGE: f64 = 1.0e-3;
+/// Probabilities are clamped to this distance from 0 and 1 to keep logarithms finite
+const MIN_PROBABILITY: f64 = 1.0e-12;
+
+/// Statistical model of paired comparisons
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Model {
+    /// P(b preferred over a) = 1 / (1 + exp(-(s_b - s_a)))
+    BradleyTerry,
+    /// Thurstone Case V: P(b preferred over a) = Phi(s_b - s_a)
+    Thurstone,
+}
+
+/// A graded comparison between two samples
+#[derive(Debug, Clone, Copy)]
+pub struct Comparison {
+    pub a: usize,
+    pub b: usize,
+    /// How strongly `b` was preferred over `a`, from 0 (only `a`) to 1 (only `b`)
+    pub preference: f64,
+}
+
+/// Result of fitting a model to a set of comparisons
+#[derive(Debug)]
+pub struct Fit {
+    /// Score of each sample, centered on 0
+    pub scores: Vec<f64>,
+    pub standard_errors: Vec<f64>,
+    pub log_likelihood: f64,
+}
+
+/// Fit the model to the comparisons of `num` samples through Fisher scoring.
+///
+/// Graded preferences are treated as fractional wins, giving a quasi-likelihood fit. Returns
+/// `None` if the comparisons do not connect all samples, since their relative scores are then
+/// undefined.
+pub fn fit(model: Model, comparisons: &[Comparison], num: usize) -> Option<Fit> {
+    if !is_connected(comparisons, num) {
+        return None;
+    }
+
+    let mut scores = DVector::<f64>::from_element(num, 0.0);
+    let mut covariance = DMatrix::<f64>::from_element(num, num, 0.0);
+
+    for _ in 0..MAX_ITERATIONS {
+        let (gradient, information) = score_and_information(model, comparisons, &scores);
+
+        // The information matrix is singular in the direction of adding a constant to all
+        // scores, so that direction is fixed by adding a matrix of ones.
+        let system = information + DMatrix::<f64>::from_element(num, num, 1.0);
+        let inverse = match system.try_inverse() {
+            Some(inverse) => inverse,
+            None => return None,
+        };
+
+        let step = &inverse * gradient;
+        let change = step.iter().fold(0.0, |max, s| f64::max(max, s.abs()));
+        scores = scores + step;
+        covariance = inverse;
+
+        if change < TOLERANCE {
+            break;
+        }
+    }
+
+    // Remove the contribution of the matrix of ones to get the pseudo-inverse
+    let offset = 1.0 / (num as f64 * (num as f64 + RIDGE));
+    let standard_errors = (0..num)
+        .map(|i| f64::max(covariance[(i, i)] - offset, 0.0).sqrt())
+        .collect();
+
+    Some(Fit {
+        log_likelihood: log_likelihood(model, comparisons, &scores),
+        scores: scores.iter().cloned().collect(),
+        standard_errors: standard_errors,
+    })
+}
+
+fn score_and_information(
+    model: Model,
+    comparisons: &[Comparison],
+    scores: &DVector<f64>,
+) -> (DVector<f64>, DMatrix<f64>) {
+    let num = scores.len();
+    let mut gradient = DVector::<f64>::from_element(num, 0.0);
+    let mut information = DMatrix::<f64>::from_element(num, num, 0.0);
+
+    for i in 0..num {
+        gradient[i] -= RIDGE * scores[i];
+        information[(i, i)] += RIDGE;
+    }
+
+    for comparison in comparisons {
+        let Comparison { a, b, preference } = *comparison;
+        let difference = scores[b] - scores[a];
+        let probability = clamp_probability(cdf(model, difference));
+        let density = pdf(model, difference);
+        let variance = probability * (1.0 - probability);
+
+        let derivative = (preference - probability) * density / variance;
+        let weight = density * density / variance;
+
+        gradient[b] += derivative;
+        gradient[a] -= derivative;
+        information[(a, a)] += weight;
+        information[(b, b)] += weight;
+        information[(a, b)] -= weight;
+        information[(b, a)] -= weight;
+    }
+
+    (gradient, information)
+}
+
+fn log_likelihood(model: Model, comparisons: &[Comparison], scores: &DVector<f64>) -> f64 {
+    comparisons
+        .iter()
+        .map(|comparison| {
+            let probability =
+                clamp_probability(cdf(model, scores[comparison.b] - scores[comparison.a]));
+            comparison.preference * probability.ln()
+                + (1.0 - comparison.preference) * (1.0 - probability).ln()
+        })
+        .sum()
+}
+
+fn cdf(model: Model, difference: f64) -> f64 {
+    match model {
+        Model::BradleyTerry => 1.0 / (1.0 + (-difference).exp()),
+        Model::Thurstone => normal_cdf(difference),
+    }
+}
+
+fn pdf(model: Model, difference: f64) -> f64 {
+    match model {
+        Model::BradleyTerry => {
+            let probability = cdf(model, difference);
+            probability * (1.0 - probability)
+        }
+        Model::Thurstone => normal_pdf(difference),
+    }
+}
+
+fn clamp_probability(probability: f64) -> f64 {
+    f64::min(f64::max(probability, MIN_PROBABILITY), 1.0 - MIN_PROBABILITY)
+}
+
+fn is_connected(comparisons: &[Comparison], num: usize) -> bool {
+    // Union-find over the compared pairs
+    let mut parents: Vec<usize> = (0..num).collect();
+
+    fn find(parents: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parents[root] != root {
+            root = parents[root];
+        }
+        parents[i] = root;
+        root
+    }
+
+    for comparison in comparisons {
+        let root_a = find(&mut parents, comparison.a);
+        let root_b = find(&mut parents, comparison.b);
+        parents[root_a] = root_b;
+    }
+
+    let roots: Vec<usize> = (0..num).map(|i| find(&mut parents, i)).collect();
+    roots.iter().all(|&root| root == roots[0])
+}
+
+#[test]
+fn test_fit() {
+    // Sample 2 is preferred over 1, which is preferred over 0
+    let comparisons = [
+        Comparison {
+            a: 0,
+            b: 1,
+            preference: 0.75,
+        },
+        Comparison {
+            a: 1,
+            b: 2,
+            preference: 0.75,
+        },
+        Comparison {
+            a: 0,
+            b: 2,
+            preference: 0.9,
+        },
+    ];
+
+    for &model in &[Model::BradleyTerry, Model::Thurstone] {
+        let result = fit(model, &comparisons, 3).unwrap();
+        assert!(result.scores[0] < result.scores[1]);
+        assert!(result.scores[1] < result.scores[2]);
+        assert!(result.scores.iter().sum::<f64>().abs() < 1.0e-6);
+        assert!(result.standard_errors.iter().all(|&e| e > 0.0));
+        assert!(result.log_likelihood < 0.0);
+    }
+
+    assert!(fit(Model::BradleyTerry, &comparisons[..1], 3).is_none());
+}
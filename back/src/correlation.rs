@@ -0,0 +1,197 @@
+use distribution::{normal_two_sided_p, student_t_two_sided_p};
+
+/// A rank correlation coefficient together with its two-sided p-value under the null hypothesis
+/// of no association
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Correlation {
+    pub coefficient: f64,
+    pub p_value: f64,
+}
+
+/// Kendall's tau-b between two paired sets of values, correcting for ties.
+///
+/// The p-value uses the normal approximation of the distribution of Kendall's S with the
+/// variance adjusted for ties. Returns `None` when there are fewer than two values or when all
+/// values of either set are tied.
+pub fn kendall_tau(x: &[f64], y: &[f64]) -> Option<Correlation> {
+    assert_eq!(x.len(), y.len());
+    let n = x.len();
+    if n < 2 {
+        return None;
+    }
+
+    let mut s = 0.0;
+    for i in 0..n {
+        for j in (i + 1)..n {
+            s += sign(x[i] - x[j]) * sign(y[i] - y[j]);
+        }
+    }
+
+    let pairs = (n * (n - 1)) as f64 / 2.0;
+    let ties_x = tie_counts(x);
+    let ties_y = tie_counts(y);
+    let tied_pairs_x = ties_x.iter().map(|&t| t * (t - 1.0) / 2.0).sum::<f64>();
+    let tied_pairs_y = ties_y.iter().map(|&t| t * (t - 1.0) / 2.0).sum::<f64>();
+
+    let denominator = ((pairs - tied_pairs_x) * (pairs - tied_pairs_y)).sqrt();
+    if denominator == 0.0 {
+        return None;
+    }
+    let tau = s / denominator;
+
+    let n = n as f64;
+    fn variance_term(t: f64) -> f64 {
+        t * (t - 1.0) * (2.0 * t + 5.0)
+    }
+    fn sum_of<F: Fn(f64) -> f64>(ties: &[f64], f: F) -> f64 {
+        ties.iter().map(|&t| f(t)).sum()
+    }
+    let mut variance = (variance_term(n) - sum_of(&ties_x, variance_term)
+        - sum_of(&ties_y, variance_term)) / 18.0;
+    if n > 2.0 {
+        variance += sum_of(&ties_x, |t| t * (t - 1.0) * (t - 2.0))
+            * sum_of(&ties_y, |t| t * (t - 1.0) * (t - 2.0))
+            / (9.0 * n * (n - 1.0) * (n - 2.0));
+    }
+    variance += sum_of(&ties_x, |t| t * (t - 1.0)) * sum_of(&ties_y, |t| t * (t - 1.0))
+        / (2.0 * n * (n - 1.0));
+
+    let p_value = if variance > 0.0 {
+        normal_two_sided_p(s / variance.sqrt())
+    } else {
+        1.0
+    };
+
+    Some(Correlation {
+        coefficient: tau,
+        p_value: p_value,
+    })
+}
+
+/// Spearman's rho between two paired sets of values, using average ranks for ties.
+///
+/// The p-value uses the t-distribution with n - 2 degrees of freedom. Returns `None` when there
+/// are fewer than three values or when all values of either set are tied.
+pub fn spearman_rho(x: &[f64], y: &[f64]) -> Option<Correlation> {
+    assert_eq!(x.len(), y.len());
+    let n = x.len();
+    if n < 3 {
+        return None;
+    }
+
+    let rho = match pearson(&average_ranks(x), &average_ranks(y)) {
+        Some(rho) => rho,
+        None => return None,
+    };
+
+    let degrees_of_freedom = (n - 2) as f64;
+    let p_value = if rho.abs() >= 1.0 {
+        0.0
+    } else {
+        let t = rho * (degrees_of_freedom / (1.0 - rho * rho)).sqrt();
+        student_t_two_sided_p(t, degrees_of_freedom)
+    };
+
+    Some(Correlation {
+        coefficient: rho,
+        p_value: p_value,
+    })
+}
+
+/// Rank of each value, starting at 1, where tied values share the average of their ranks
+pub fn average_ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].partial_cmp(&values[b]).unwrap());
+
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        // Ranks start + 1 through end are tied
+        let rank = (start + 1 + end) as f64 / 2.0;
+        for &index in &order[start..end] {
+            ranks[index] = rank;
+        }
+        start = end;
+    }
+
+    ranks
+}
+
+/// Sizes of the groups of tied values, leaving out values without ties
+fn tie_counts(values: &[f64]) -> Vec<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut counts = Vec::new();
+    let mut start = 0;
+    while start < sorted.len() {
+        let mut end = start + 1;
+        while end < sorted.len() && sorted[end] == sorted[start] {
+            end += 1;
+        }
+        if end - start > 1 {
+            counts.push((end - start) as f64);
+        }
+        start = end;
+    }
+
+    counts
+}
+
+/// Pearson correlation coefficient between two paired sets of values, or `None` when all values
+/// of either set are equal
+pub fn pearson(x: &[f64], y: &[f64]) -> Option<f64> {
+    let n = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / n;
+    let mean_y = y.iter().sum::<f64>() / n;
+
+    let mut covariance = 0.0;
+    let mut variance_x = 0.0;
+    let mut variance_y = 0.0;
+    for (&a, &b) in x.iter().zip(y) {
+        covariance += (a - mean_x) * (b - mean_y);
+        variance_x += (a - mean_x) * (a - mean_x);
+        variance_y += (b - mean_y) * (b - mean_y);
+    }
+
+    if variance_x == 0.0 || variance_y == 0.0 {
+        None
+    } else {
+        Some(covariance / (variance_x * variance_y).sqrt())
+    }
+}
+
+fn sign(value: f64) -> f64 {
+    if value > 0.0 {
+        1.0
+    } else if value < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+#[test]
+fn test_rank_correlation() {
+    let x = [1.0, 2.0, 3.0, 4.0, 5.0];
+    let reversed = [5.0, 4.0, 3.0, 2.0, 1.0];
+    assert!((kendall_tau(&x, &x).unwrap().coefficient - 1.0).abs() < 1.0e-9);
+    assert!((kendall_tau(&x, &reversed).unwrap().coefficient + 1.0).abs() < 1.0e-9);
+    assert!((spearman_rho(&x, &reversed).unwrap().coefficient + 1.0).abs() < 1.0e-9);
+
+    // Known values with ties in both sets
+    let x = [1.0, 2.0, 2.0, 3.0, 4.0, 5.0];
+    let y = [2.0, 1.0, 3.0, 3.0, 5.0, 4.0];
+    let tau = kendall_tau(&x, &y).unwrap();
+    assert!((tau.coefficient - 0.642_857_14).abs() < 1.0e-6);
+    let rho = spearman_rho(&x, &y).unwrap();
+    assert!((rho.coefficient - 0.808_823_53).abs() < 1.0e-6);
+    assert!((rho.p_value - 0.051_329_06).abs() < 1.0e-4);
+
+    assert_eq!(average_ranks(&[3.0, 1.0, 3.0, 2.0]), vec![3.5, 1.0, 3.5, 2.0]);
+    assert!(kendall_tau(&x, &[1.0; 6]).is_none());
+}
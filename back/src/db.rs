@@ -1,21 +1,48 @@
+use bson::{from_bson, to_bson, Bson, Document};
 use bson::oid::ObjectId;
 use chrono::{NaiveDateTime, Utc};
-use mongodb::{self, Client, ThreadedClient};
-use mongodb::db::ThreadedDatabase;
-use mongodb::coll::options::{IndexModel, IndexOptions};
+use mongodb::{self, Client, ClientOptions, CommandType, ThreadedClient};
+use mongodb::coll::Collection;
+use mongodb::coll::error::WriteException;
+use mongodb::coll::options::{IndexModel, IndexOptions, ReplaceOptions, UpdateOptions};
+use mongodb::coll::results::{InsertOneResult, UpdateResult};
+use mongodb::connstring;
+use mongodb::db::{Database, ThreadedDatabase};
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
 use uuid::Uuid;
 
+use manifest::Manifest;
 use model::{self, Browser, Education, Gender, Metric, Occupation, PostQuestionnaire,
-            PreQuestionnaire};
+            PreQuestionnaire, Sample};
+use storage::{self, Storage, WeightFilter};
 use cfg;
 
-pub const NAME: &str = "lsys-pairwise";
 pub const COLLECTION_SAMPLE: &str = "sample";
 pub const COLLECTION_USER: &str = "user";
 pub const COLLECTION_WEIGHT: &str = "weight";
+pub const COLLECTION_TASK: &str = "task";
+pub const COLLECTION_PRESENTATION: &str = "presentation";
+/// Side imbalance of each sample of a task, with the sample as a hex ID
+pub const COLLECTION_SIDE_IMBALANCE: &str = "side_imbalance";
+
+/// Version of the schema of the users and weights stored now, which `migrate` upgrades older
+/// records to
+pub const SCHEMA_VERSION: i32 = 1;
+
+/// A task and its settings, registered when the task directories are scanned
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Task {
+    pub name: String,
+    pub manifest: Manifest,
+}
 
 /// A user representation in the database
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct User {
     pub age: i32,
     pub gender: Gender,
@@ -35,6 +62,27 @@ pub struct User {
     pub pre_questionnaire: Option<PreQuestionnaire>,
     pub post_questionnaire: Option<PostQuestionnaire>,
     pub browser: Option<Browser>,
+    /// Pairs assigned to the user in a balanced task, once the task has been requested
+    pub assignment: Option<Vec<(ObjectId, ObjectId)>>,
+    /// Seed of the pair order and sides, missing for users registered before it was introduced
+    pub seed: Option<i64>,
+    /// Version of the schema the user was stored with, 0 for users stored before versions were
+    /// recorded
+    #[serde(default)]
+    pub schema_version: i32,
+}
+
+impl User {
+    /// Seed of the pair order and sides, falling back to one derived from the token
+    pub fn seed(&self) -> usize {
+        match self.seed {
+            Some(seed) => seed as usize,
+            None => {
+                let prefix: String = self.token.chars().take(16).collect();
+                u64::from_str_radix(&prefix, 16).unwrap_or(0) as usize
+            }
+        }
+    }
 }
 
 impl From<model::User> for User {
@@ -53,11 +101,14 @@ impl From<model::User> for User {
             pre_questionnaire: user.pre_questionnaire,
             post_questionnaire: None,
             browser: user.browser,
+            assignment: None,
+            seed: Some(thread_rng().gen()),
+            schema_version: SCHEMA_VERSION,
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Weighting {
     pub token: String,
     pub fullscreen: bool,
@@ -67,6 +118,31 @@ pub struct Weighting {
     pub b: ObjectId,
     pub weight: f32,
     pub time: NaiveDateTime,
+    /// Whether the weight is a repeat measurement of a pair weighted earlier by the user
+    #[serde(default)]
+    pub repeat: bool,
+    /// Version of the schema the weight was stored with, 0 for weights stored before versions
+    /// were recorded
+    #[serde(default)]
+    pub schema_version: i32,
+}
+
+/// A pair as it was served to a user
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Presentation {
+    pub token: String,
+    pub task: String,
+    /// Sample shown on the left
+    pub a: ObjectId,
+    /// Sample shown on the right
+    pub b: ObjectId,
+    /// Position of the pair in the order the user was served pairs, starting at 0
+    pub position: i32,
+    /// Whether the pair is a repeat of a pair served earlier, with the sides swapped
+    #[serde(default)]
+    pub repeat: bool,
+    /// When the pair was first served
+    pub time: NaiveDateTime,
 }
 
 impl From<model::Weighting> for Weighting {
@@ -80,6 +156,8 @@ impl From<model::Weighting> for Weighting {
             b: ObjectId::with_string(&weighting.b).unwrap(),
             weight: weighting.weight,
             time: Utc::now().naive_utc(),
+            repeat: weighting.repeat,
+            schema_version: SCHEMA_VERSION,
         }
     }
 }
@@ -98,13 +176,342 @@ impl Into<model::Weighting> for Weighting {
             a: self.a.to_hex(),
             b: self.b.to_hex(),
             weight: self.weight,
+            repeat: self.repeat,
+        }
+    }
+}
+
+
+/// Storage in a MongoDB database
+pub struct MongoStorage {
+    client: Client,
+    /// Name of the database of the studies
+    name: String,
+}
+
+impl MongoStorage {
+    /// Connect to MongoDB, trying again after a delay until the configured number of attempts
+    /// have failed
+    pub fn connect(db_cfg: &cfg::Db) -> Result<MongoStorage, storage::Error> {
+        if let Some(pool_size) = db_cfg.pool_size {
+            println!(
+                "Warning: The MongoDB driver cannot set the pool size, so {} is ignored",
+                pool_size
+            );
+        }
+
+        let mut attempt = 1;
+        loop {
+            match MongoStorage::try_connect(db_cfg) {
+                Ok(storage) => return Ok(storage),
+                // Settings that are wrong do not get better by trying again
+                Err(error @ mongodb::Error::ArgumentError(_)) => {
+                    return Err(from_mongodb_error(error))
+                }
+                Err(error) => {
+                    if attempt >= db_cfg.connect_attempts {
+                        return Err(from_mongodb_error(error));
+                    }
+
+                    println!(
+                        "Failed to connect to MongoDB (attempt {} of {}): {}",
+                        attempt, db_cfg.connect_attempts, error
+                    );
+                    thread::sleep(Duration::from_secs(db_cfg.retry_delay));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn try_connect(db_cfg: &cfg::Db) -> mongodb::Result<MongoStorage> {
+        let uri = match db_cfg.uri {
+            Some(ref uri) => uri.clone(),
+            None => format!("mongodb://{}:{}", db_cfg.host, db_cfg.port),
+        };
+        let config = connstring::parse(&uri)?;
+
+        // Like in other MongoDB clients, the database of the URI is where users are defined
+        let user = db_cfg.user.clone().or_else(|| config.user.clone());
+        let password = db_cfg.password.clone().or_else(|| config.password.clone());
+        let auth_source = db_cfg
+            .auth_source
+            .clone()
+            .or_else(|| config.database.clone())
+            .unwrap_or_else(|| db_cfg.name.clone());
+
+        let mut options = make_client_options(db_cfg)?;
+        options.server_selection_timeout_ms = db_cfg.timeout_ms;
+        let client = Client::with_config(config, Some(options), None)?;
+
+        if let Some(user) = user {
+            client
+                .db(&auth_source)
+                .auth(&user, &password.unwrap_or_default())?;
+        }
+
+        // The client connects lazily, so check that the server can be reached
+        client
+            .db(&db_cfg.name)
+            .command(doc! { "ping": 1 }, CommandType::Suppressed, None)?;
+        println!("Connected to MongoDB database '{}'", db_cfg.name);
+
+        Ok(MongoStorage {
+            client: client,
+            name: db_cfg.name.clone(),
+        })
+    }
+
+    fn collection(&self, name: &str) -> Collection {
+        self.client.db(&self.name).collection(name)
+    }
+}
+
+impl Storage for MongoStorage {
+    fn init(&self) -> Result<(), storage::Error> {
+        init(&self.client.db(&self.name)).map_err(from_mongodb_error)
+    }
+
+    fn find_samples(&self, task: &str, include_gold: bool) -> Vec<(ObjectId, Sample)> {
+        let mut filter = doc! {
+            "task": task,
+        };
+        if !include_gold {
+            filter.insert("gold", doc! { "$ne": true });
+        }
+
+        self.collection(COLLECTION_SAMPLE)
+            .find(Some(filter), None)
+            .expect("Failed retrieving samples")
+            .map(|doc| {
+                let doc = doc.expect("Failed retrieving sample");
+                let id = doc.get_object_id("_id")
+                    .expect("Failed deserializing sample")
+                    .clone();
+                let sample = from_bson(Bson::from(doc)).expect("Failed deserializing sample");
+                (id, sample)
+            })
+            .collect()
+    }
+
+    fn find_sample(&self, id: &ObjectId) -> Option<Sample> {
+        self.collection(COLLECTION_SAMPLE)
+            .find_one(Some(doc! { "_id": id.clone() }), None)
+            .expect("Failed retrieving sample")
+            .map(|doc| from_bson(Bson::from(doc)).expect("Failed deserializing sample"))
+    }
+
+    fn find_task_names(&self) -> Vec<String> {
+        self.collection(COLLECTION_SAMPLE)
+            .distinct("task", None, None)
+            .expect("Failed retrieving samples")
+            .iter()
+            .map(|bson_value| {
+                bson_value
+                    .as_str()
+                    .expect("Some task fields were not strings")
+                    .to_string()
+            })
+            .collect()
+    }
+
+    fn save_sample(&self, sample: &Sample) -> Result<(), storage::Error> {
+        let sample_doc = to_document(sample);
+        let result = self.collection(COLLECTION_SAMPLE).replace_one(
+            doc! {
+                "task": &sample.task,
+                "name": &sample.name,
+            },
+            sample_doc,
+            Some(ReplaceOptions {
+                upsert: Some(true),
+                ..Default::default()
+            }),
+        );
+
+        check_update(result).map(|_| ())
+    }
+
+    fn find_task(&self, name: &str) -> Option<Task> {
+        self.collection(COLLECTION_TASK)
+            .find_one(Some(doc! { "name": name }), None)
+            .expect("Failed retrieving task")
+            .map(|doc| from_bson(Bson::from(doc)).expect("Failed deserializing task"))
+    }
+
+    fn save_task(&self, task: &Task) -> Result<(), storage::Error> {
+        let task_doc = to_document(task);
+        let result = self.collection(COLLECTION_TASK).replace_one(
+            doc! {
+                "name": &task.name,
+            },
+            task_doc,
+            Some(ReplaceOptions {
+                upsert: Some(true),
+                ..Default::default()
+            }),
+        );
+
+        check_update(result).map(|_| ())
+    }
+
+    fn find_user(&self, token: &str) -> Option<User> {
+        self.collection(COLLECTION_USER)
+            .find_one(Some(doc! { "token": token }), None)
+            .expect("Failed retrieving user")
+            .map(|doc| from_bson(Bson::from(doc)).expect("Failed deserializing user"))
+    }
+
+    fn find_users(&self, task: &str) -> Vec<User> {
+        find_all(&self.collection(COLLECTION_USER), doc! { "task": task })
+    }
+
+    fn insert_user(&self, user: &User) -> Result<(), storage::Error> {
+        let result = self.collection(COLLECTION_USER)
+            .insert_one(to_document(user), None);
+
+        check_insert(result)
+    }
+
+    fn update_user(&self, user: &User) -> Result<bool, storage::Error> {
+        let result = self.collection(COLLECTION_USER).replace_one(
+            doc! {
+                "token": &user.token,
+            },
+            to_document(user),
+            None,
+        );
+
+        check_update(result).map(|result| result.matched_count > 0)
+    }
+
+    fn find_weightings(&self, filter: &WeightFilter) -> Vec<Weighting> {
+        find_all(&self.collection(COLLECTION_WEIGHT), make_weight_query(filter))
+    }
+
+    fn insert_weighting(&self, weighting: &Weighting) -> Result<(), storage::Error> {
+        let result = self.collection(COLLECTION_WEIGHT)
+            .insert_one(to_document(weighting), None);
+
+        check_insert(result)
+    }
+
+    fn update_weighting(&self, weighting: &Weighting) -> Result<bool, storage::Error> {
+        let filter = WeightFilter {
+            token: Some(weighting.token.clone()),
+            metric: Some(weighting.metric.clone()),
+            pair: Some((weighting.a.clone(), weighting.b.clone())),
+            repeat: Some(weighting.repeat),
+            ..Default::default()
+        };
+        let result = self.collection(COLLECTION_WEIGHT).replace_one(
+            make_weight_query(&filter),
+            to_document(weighting),
+            None,
+        );
+
+        check_update(result).map(|result| result.matched_count > 0)
+    }
+
+    fn find_user_presentations(&self, token: &str) -> Vec<Presentation> {
+        find_all(
+            &self.collection(COLLECTION_PRESENTATION),
+            doc! { "token": token },
+        )
+    }
+
+    fn find_side_imbalance(&self, task: &str) -> HashMap<String, i32> {
+        self.collection(COLLECTION_SIDE_IMBALANCE)
+            .find(Some(doc! { "task": task }), None)
+            .expect("Failed retrieving side imbalance")
+            .map(|doc| {
+                let doc = doc.expect("Failed retrieving side imbalance");
+                let sample = doc.get_str("sample")
+                    .expect("Failed deserializing side imbalance")
+                    .to_string();
+                let imbalance = doc.get_i32("imbalance")
+                    .expect("Failed deserializing side imbalance");
+                (sample, imbalance)
+            })
+            .collect()
+    }
+
+    fn record_presentation(&self, presentation: &Presentation) -> Result<(), storage::Error> {
+        let result = self.collection(COLLECTION_PRESENTATION).update_one(
+            doc! {
+                "token": &presentation.token,
+                "a": presentation.a.clone(),
+                "b": presentation.b.clone(),
+            },
+            doc! {
+                "$setOnInsert": to_document(presentation),
+            },
+            Some(UpdateOptions {
+                upsert: Some(true),
+                ..Default::default()
+            }),
+        );
+        if check_update(result)?.upserted_id.is_none() {
+            return Ok(());
+        }
+
+        for &(sample, side) in &[(&presentation.a, 1), (&presentation.b, -1)] {
+            let result = self.collection(COLLECTION_SIDE_IMBALANCE).update_one(
+                doc! {
+                    "task": &presentation.task,
+                    "sample": sample.to_hex(),
+                },
+                doc! {
+                    "$inc": { "imbalance": side },
+                },
+                Some(UpdateOptions {
+                    upsert: Some(true),
+                    ..Default::default()
+                }),
+            );
+            check_update(result)?;
         }
+        Ok(())
     }
 }
 
-pub fn init(db_client: &mongodb::Client) -> mongodb::Result<()> {
-    let db = db_client.db(NAME);
+/// Options of the client, which connects over TLS if it is configured
+#[cfg(feature = "ssl")]
+fn make_client_options(db_cfg: &cfg::Db) -> mongodb::Result<ClientOptions> {
+    if !db_cfg.tls {
+        return Ok(ClientOptions::new());
+    }
+
+    let ca_file = db_cfg.tls_ca_file.as_ref().map(|file| file.as_str());
+    match (&db_cfg.tls_cert_file, &db_cfg.tls_key_file) {
+        (&Some(ref cert_file), &Some(ref key_file)) => Ok(ClientOptions::with_ssl(
+            ca_file,
+            cert_file,
+            key_file,
+            db_cfg.tls_verify,
+        )),
+        (&None, &None) => Ok(ClientOptions::with_unauthenticated_ssl(
+            ca_file,
+            db_cfg.tls_verify,
+        )),
+        _ => Err(mongodb::Error::ArgumentError(
+            "A TLS client certificate needs both a certificate and a key file".to_string(),
+        )),
+    }
+}
+
+#[cfg(not(feature = "ssl"))]
+fn make_client_options(db_cfg: &cfg::Db) -> mongodb::Result<ClientOptions> {
+    if db_cfg.tls {
+        return Err(mongodb::Error::ArgumentError(
+            "TLS needs the server to be built with the ssl feature".to_string(),
+        ));
+    }
+
+    Ok(ClientOptions::new())
+}
 
+fn init(db: &Database) -> mongodb::Result<()> {
     db.collection(COLLECTION_SAMPLE).create_indexes(vec![
         IndexModel::new(
             doc! { "task": 1, "name": 1 },
@@ -138,9 +545,38 @@ pub fn init(db_client: &mongodb::Client) -> mongodb::Result<()> {
         }),
     )?;
 
+    db.collection(COLLECTION_TASK).create_index(
+        doc! { "name": 1 },
+        Some(IndexOptions {
+            unique: Some(true),
+            ..Default::default()
+        }),
+    )?;
+
+    db.collection(COLLECTION_PRESENTATION).create_index(
+        doc! { "token": 1, "a": 1, "b": 1 },
+        Some(IndexOptions {
+            unique: Some(true),
+            ..Default::default()
+        }),
+    )?;
+
+    db.collection(COLLECTION_SIDE_IMBALANCE).create_index(
+        doc! { "task": 1, "sample": 1 },
+        Some(IndexOptions {
+            unique: Some(true),
+            ..Default::default()
+        }),
+    )?;
+    count_side_imbalance(db)?;
+
+    // Repeated pairs are weighted twice, so the index without the repeat flag no longer holds
+    let _ = db.collection(COLLECTION_WEIGHT)
+        .drop_index_string("token_1_metric_1_a_1_b_1".to_string());
+
     db.collection(COLLECTION_WEIGHT).create_indexes(vec![
         IndexModel::new(
-            doc! { "token": 1, "metric": 1, "a": 1, "b": 1 },
+            doc! { "token": 1, "metric": 1, "a": 1, "b": 1, "repeat": 1 },
             Some(IndexOptions {
                 unique: Some(true),
                 ..Default::default()
@@ -172,10 +608,143 @@ pub fn init(db_client: &mongodb::Client) -> mongodb::Result<()> {
     Ok(())
 }
 
-pub fn connect(db_cfg: &cfg::Db) -> Client {
-    let client = Client::connect(&db_cfg.host, 27_017)
-        .expect(&format!("Failed to connect to DB at {}", db_cfg.host));
-    println!("Connected to MongoDB at {}", db_cfg.host);
+/// Count the side imbalance from the presentations recorded before it was kept
+fn count_side_imbalance(db: &Database) -> mongodb::Result<()> {
+    let collection = db.collection(COLLECTION_SIDE_IMBALANCE);
+    if collection.count(None, None)? > 0 {
+        return Ok(());
+    }
 
-    client
+    let presentations: Vec<Presentation> =
+        find_all(&db.collection(COLLECTION_PRESENTATION), Document::new());
+    let mut imbalance: HashMap<(String, String), i32> = HashMap::new();
+    for presentation in presentations {
+        let task = presentation.task;
+        *imbalance
+            .entry((task.clone(), presentation.a.to_hex()))
+            .or_insert(0) += 1;
+        *imbalance.entry((task, presentation.b.to_hex())).or_insert(0) -= 1;
+    }
+    if imbalance.is_empty() {
+        return Ok(());
+    }
+
+    let docs = imbalance
+        .into_iter()
+        .map(|((task, sample), imbalance)| {
+            doc! {
+                "task": task,
+                "sample": sample,
+                "imbalance": imbalance,
+            }
+        })
+        .collect();
+    collection.insert_many(docs, None)?;
+
+    Ok(())
+}
+
+/// Query of the weights that pass the filter
+fn make_weight_query(filter: &WeightFilter) -> Document {
+    let mut conditions = Vec::new();
+    if let Some(ref token) = filter.token {
+        conditions.push(doc! { "token": token.as_str() });
+    }
+    if let Some(ref tokens) = filter.tokens {
+        let tokens = to_bson(tokens).unwrap();
+        conditions.push(doc! { "token": { "$in": tokens } });
+    }
+    if let Some(ref metric) = filter.metric {
+        conditions.push(doc! { "metric": metric.as_str() });
+    }
+    if let Some(ref a_in) = filter.a_in {
+        let ids = to_bson(a_in).unwrap();
+        conditions.push(doc! { "a": { "$in": ids } });
+    }
+    if let Some(ref sample_in) = filter.sample_in {
+        let ids = to_bson(sample_in).unwrap();
+        conditions.push(doc! {
+            "$or": [
+                { "a": { "$in": ids.clone() } },
+                { "b": { "$in": ids } }
+            ],
+        });
+    }
+    if let Some((ref a, ref b)) = filter.pair {
+        conditions.push(doc! { "a": a.clone(), "b": b.clone() });
+    }
+    match filter.repeat {
+        Some(true) => conditions.push(doc! { "repeat": true }),
+        // Weights stored before repeats were introduced have no repeat flag
+        Some(false) => conditions.push(doc! { "repeat": { "$ne": true } }),
+        None => {}
+    }
+
+    if conditions.is_empty() {
+        Document::new()
+    } else {
+        let conditions: Vec<Bson> = conditions.into_iter().map(Bson::Document).collect();
+        doc! { "$and": conditions }
+    }
+}
+
+fn find_all<T: DeserializeOwned>(collection: &Collection, filter: Document) -> Vec<T> {
+    collection
+        .find(Some(filter), None)
+        .expect("Failed querying DB")
+        .map(|doc| {
+            from_bson(Bson::from(doc.expect("Failed retrieving document")))
+                .expect("Failed deserializing document")
+        })
+        .collect()
+}
+
+fn to_document<T: Serialize>(value: &T) -> Document {
+    match to_bson(value).expect("Failed serializing document") {
+        Bson::Document(document) => document,
+        _ => panic!("Failed serializing document: Not a document"),
+    }
+}
+
+/// Error code of MongoDB when a unique index is violated
+const DUPLICATE_KEY: i32 = 11_000;
+
+fn check_insert(result: mongodb::Result<InsertOneResult>) -> Result<(), storage::Error> {
+    let result = result.map_err(from_mongodb_error)?;
+    if let Some(exception) = result.write_exception {
+        return Err(from_write_exception(exception));
+    }
+    if !result.acknowledged {
+        return Err(storage::Error::Backend(
+            "Insertion not acknowledged".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_update(result: mongodb::Result<UpdateResult>) -> Result<UpdateResult, storage::Error> {
+    let result = result.map_err(from_mongodb_error)?;
+    if let Some(exception) = result.write_exception {
+        return Err(from_write_exception(exception));
+    }
+    if !result.acknowledged {
+        return Err(storage::Error::Backend("Update not acknowledged".to_string()));
+    }
+
+    Ok(result)
+}
+
+fn from_write_exception(exception: WriteException) -> storage::Error {
+    match exception.write_error {
+        Some(ref error) if error.code == DUPLICATE_KEY => storage::Error::Duplicate,
+        _ => storage::Error::Backend(exception.message),
+    }
+}
+
+fn from_mongodb_error(error: mongodb::Error) -> storage::Error {
+    match error {
+        mongodb::Error::WriteError(exception) => from_write_exception(exception),
+        error => storage::Error::Backend(error.to_string()),
+    }
 }
@@ -0,0 +1,251 @@
+use std::f64::consts::{PI, SQRT_2};
+
+/// Cumulative distribution function of the standard normal distribution
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / SQRT_2)
+}
+
+/// Probability density function of the standard normal distribution
+pub fn normal_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * PI).sqrt()
+}
+
+/// Two-sided p-value of a statistic that follows the standard normal distribution
+pub fn normal_two_sided_p(z: f64) -> f64 {
+    2.0 * (1.0 - normal_cdf(z.abs()))
+}
+
+/// Two-sided p-value of a statistic that follows Student's t-distribution
+pub fn student_t_two_sided_p(t: f64, degrees_of_freedom: f64) -> f64 {
+    incomplete_beta(
+        degrees_of_freedom / (degrees_of_freedom + t * t),
+        degrees_of_freedom / 2.0,
+        0.5,
+    )
+}
+
+/// Probability of a chi-squared distributed statistic being at least `x`
+pub fn chi_square_upper_p(x: f64, degrees_of_freedom: f64) -> f64 {
+    1.0 - incomplete_gamma(degrees_of_freedom / 2.0, x / 2.0)
+}
+
+/// Two-sided p-value of an exact binomial test of `successes` out of `trials` when the
+/// probability of success is 0.5, such as a sign test
+pub fn binomial_two_sided_p(successes: usize, trials: usize) -> f64 {
+    if trials == 0 {
+        return 1.0;
+    }
+
+    let n = trials as f64;
+    let tail = usize::min(successes, trials - successes);
+    let probability: f64 = (0..(tail + 1))
+        .map(|k| {
+            let k = k as f64;
+            (ln_gamma(n + 1.0) - ln_gamma(k + 1.0) - ln_gamma(n - k + 1.0) - n * 2f64.ln()).exp()
+        })
+        .sum();
+
+    f64::min(2.0 * probability, 1.0)
+}
+
+/// Natural logarithm of the gamma function, using the Lanczos approximation
+pub fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.120_865_097_386_617_9e-2,
+        -0.539_523_938_495_3e-5,
+    ];
+
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000_000_000_190_015;
+    for (i, coefficient) in COEFFICIENTS.iter().enumerate() {
+        series += coefficient / (x + 1.0 + i as f64);
+    }
+
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+/// Regularized incomplete beta function I_x(a, b)
+pub fn incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln())
+        .exp();
+
+    // The continued fraction converges quickly on one side of the mean, so use the symmetry
+    // I_x(a, b) = 1 - I_(1-x)(b, a) on the other.
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(x, a, b) / a
+    } else {
+        1.0 - front * beta_continued_fraction(1.0 - x, b, a) / b
+    }
+}
+
+/// Regularized lower incomplete gamma function P(a, x)
+pub fn incomplete_gamma(a: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 200;
+    const EPSILON: f64 = 3.0e-14;
+    const TINY: f64 = 1.0e-300;
+
+    if x <= 0.0 {
+        return 0.0;
+    }
+
+    let front = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        // Series representation
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..MAX_ITERATIONS {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        sum * front
+    } else {
+        // Continued fraction of the upper function, evaluated with Lentz's method
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / TINY;
+        let mut d = 1.0 / b;
+        let mut result = d;
+        for i in 1..MAX_ITERATIONS {
+            let numerator = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = numerator * d + b;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = b + numerator / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            result *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+        1.0 - front * result
+    }
+}
+
+/// Continued fraction of the incomplete beta function, evaluated with Lentz's method
+fn beta_continued_fraction(x: f64, a: f64, b: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 200;
+    const EPSILON: f64 = 3.0e-14;
+    const TINY: f64 = 1.0e-300;
+
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut result = d;
+
+    for m in 1..MAX_ITERATIONS {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+
+        // Even step
+        let numerator = m * (b - m) * x / ((a + m2 - 1.0) * (a + m2));
+        d = 1.0 + numerator * d;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = 1.0 + numerator / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        result *= d * c;
+
+        // Odd step
+        let numerator = -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0));
+        d = 1.0 + numerator * d;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = 1.0 + numerator / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        result *= delta;
+
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+
+    result
+}
+
+/// Complementary error function, with fractional error below 1.2e-7.
+///
+/// Chebyshev approximation from Numerical Recipes (erfcc).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = -1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87
+                                    + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let result = t * (-z * z + polynomial).exp();
+
+    if x >= 0.0 {
+        result
+    } else {
+        2.0 - result
+    }
+}
+
+#[test]
+fn test_normal_cdf() {
+    assert!((normal_cdf(0.0) - 0.5).abs() < 1.0e-7);
+    assert!((normal_cdf(1.959_964) - 0.975).abs() < 1.0e-6);
+    assert!((normal_cdf(-1.0) - 0.158_655_25).abs() < 1.0e-6);
+}
+
+#[test]
+fn test_student_t() {
+    // Critical values of the two-sided 5 % level
+    assert!((student_t_two_sided_p(12.706_2, 1.0) - 0.05).abs() < 1.0e-4);
+    assert!((student_t_two_sided_p(2.228_1, 10.0) - 0.05).abs() < 1.0e-4);
+    assert!((student_t_two_sided_p(0.0, 5.0) - 1.0).abs() < 1.0e-9);
+}
+
+#[test]
+fn test_chi_square() {
+    // Critical values of the 5 % level
+    assert!((chi_square_upper_p(3.841_459, 1.0) - 0.05).abs() < 1.0e-6);
+    assert!((chi_square_upper_p(18.307_04, 10.0) - 0.05).abs() < 1.0e-6);
+    assert!((chi_square_upper_p(0.0, 3.0) - 1.0).abs() < 1.0e-9);
+}
+
+#[test]
+fn test_binomial() {
+    // P(X <= 1) = 11 / 1024 for 10 fair coin flips
+    assert!((binomial_two_sided_p(1, 10) - 22.0 / 1024.0).abs() < 1.0e-9);
+    assert!((binomial_two_sided_p(9, 10) - 22.0 / 1024.0).abs() < 1.0e-9);
+    assert!((binomial_two_sided_p(5, 10) - 1.0).abs() < 1.0e-9);
+}
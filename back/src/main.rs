@@ -3,6 +3,7 @@
 #![allow(unknown_lints)]
 #![allow(needless_pass_by_value)] // Because of Rocket passing things like State<T> as values
 
+extern crate base64;
 #[macro_use]
 extern crate bson;
 extern crate chrono;
@@ -15,38 +16,118 @@ extern crate rocket;
 #[macro_use]
 extern crate rocket_contrib;
 extern crate rocket_cors;
+extern crate rusqlite;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate serde_yaml;
 extern crate uuid;
 
 mod cfg;
 mod routes;
 mod db;
+mod storage;
+mod memory;
+mod sqlite;
+mod migrate;
 mod model;
 mod server;
 mod stats;
 mod serde_enum;
+mod choice_model;
+mod distribution;
+mod bootstrap;
+mod correlation;
+mod agreement;
+mod manifest;
+mod selection;
+mod scale;
 
-use bson::{from_bson, to_bson, Bson};
-use clap::{App, Arg, SubCommand};
-use mongodb::ThreadedClient;
-use mongodb::db::ThreadedDatabase;
-use mongodb::coll::options::FindOptions;
+use clap::{App, Arg, ArgMatches, SubCommand};
 use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
 
-use cfg::Config;
+use cfg::{Backend, Config};
 use model::{Metric, Weighting};
 use db::User;
+use stats::{Aggregation, PriorityMethod, RankingSource, UserFilter};
+use storage::{Storage, WeightFilter};
+use bootstrap::Resample;
 
 fn main() {
     let matches = App::new("lsys-pairwise")
         .version("0.1")
         .author("Magnus Bjerke Vik <mbvett@gmail.com>")
         .about("Pairwise comparison of LSystems")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("YAML file of settings, which environment variables and the flags below override. TOML is not supported."),
+        )
+        .arg(
+            Arg::with_name("db-backend")
+                .long("db-backend")
+                .takes_value(true)
+                .possible_values(&["mongodb", "sqlite", "memory"])
+                .help("Where the studies are stored"),
+        )
+        .arg(
+            Arg::with_name("db-uri")
+                .long("db-uri")
+                .takes_value(true)
+                .help("MongoDB connection string"),
+        )
+        .arg(
+            Arg::with_name("db-path")
+                .long("db-path")
+                .takes_value(true)
+                .help("File of the SQLite backend"),
+        )
+        .arg(
+            Arg::with_name("task-dir")
+                .long("task-dir")
+                .takes_value(true)
+                .help("Directory with a directory of videos and a manifest for each task"),
+        )
+        .arg(
+            Arg::with_name("export-dir")
+                .long("export-dir")
+                .takes_value(true)
+                .help("Directory the save-* commands write their files to"),
+        )
         .subcommand(SubCommand::with_name("server").about("Run server"))
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Inspect the configuration")
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about("Print the effective configuration, with passwords hidden, and check it"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Upgrade users and weights stored with an older schema")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Report what would be upgraded without storing anything"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("metrics")
+                .about("List the metrics of a task and the wording of their sliders")
+                .arg(
+                    Arg::with_name("task")
+                        .long("task")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Task to list metrics for"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("stats")
                 .about("Calculate statistics from data")
@@ -68,9 +149,282 @@ fn main() {
                     Arg::with_name("metric")
                         .long("metric")
                         .takes_value(true)
+                        .help("Metric to see stats for, which defaults to the first metric of the task"),
+                )
+                .arg(
+                    Arg::with_name("method")
+                        .long("method")
+                        .takes_value(true)
+                        .default_value("mean")
+                        .possible_values(&["mean", "eigenvector", "log_least_squares"])
+                        .help("Method used to calculate priorities"),
+                )
+                .arg(
+                    Arg::with_name("bootstrap")
+                        .long("bootstrap")
+                        .takes_value(true)
+                        .help("Number of bootstrap iterations for confidence intervals"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("Seed of the bootstrap random number generator"),
+                )
+                .arg(
+                    Arg::with_name("level")
+                        .long("level")
+                        .takes_value(true)
+                        .default_value("0.95")
+                        .help("Confidence level of the bootstrap intervals"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("group-stats")
+                .about("Calculate the ranking of a task from the judgments of all of its users")
+                .arg(
+                    Arg::with_name("task")
+                        .long("task")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Task to get stats for"),
+                )
+                .arg(
+                    Arg::with_name("metric")
+                        .long("metric")
+                        .takes_value(true)
+                        .help("Metric to see stats for, which defaults to the first metric of the task"),
+                )
+                .arg(
+                    Arg::with_name("method")
+                        .long("method")
+                        .takes_value(true)
+                        .default_value("mean")
+                        .possible_values(&["mean", "eigenvector", "log_least_squares"])
+                        .help("Method used to calculate priorities"),
+                )
+                .arg(
+                    Arg::with_name("aggregation")
+                        .long("aggregation")
+                        .takes_value(true)
+                        .default_value("aij")
+                        .possible_values(&["aij", "aip"])
+                        .help("Aggregate individual judgments (aij) or priorities (aip)"),
+                )
+                .arg(
+                    Arg::with_name("source")
+                        .long("source")
+                        .takes_value(true)
+                        .help("Only include users from this source"),
+                )
+                .arg(
+                    Arg::with_name("gender")
+                        .long("gender")
+                        .takes_value(true)
+                        .help("Only include users of this gender"),
+                )
+                .arg(
+                    Arg::with_name("education")
+                        .long("education")
+                        .takes_value(true)
+                        .help("Only include users with this education"),
+                )
+                .arg(
+                    Arg::with_name("complete")
+                        .long("complete")
+                        .help("Only include users that have compared all pairs"),
+                )
+                .arg(
+                    Arg::with_name("include-failed-gold")
+                        .long("include-failed-gold")
+                        .help("Also include users that failed the attention checks"),
+                )
+                .arg(
+                    Arg::with_name("bootstrap")
+                        .long("bootstrap")
+                        .takes_value(true)
+                        .help("Number of bootstrap iterations for confidence intervals"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("Seed of the bootstrap random number generator"),
+                )
+                .arg(
+                    Arg::with_name("level")
+                        .long("level")
+                        .takes_value(true)
+                        .default_value("0.95")
+                        .help("Confidence level of the bootstrap intervals"),
+                )
+                .arg(
+                    Arg::with_name("resample")
+                        .long("resample")
+                        .takes_value(true)
+                        .default_value("users")
+                        .possible_values(&["users", "comparisons"])
+                        .help("Resample users or each user's comparisons when bootstrapping"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("agreement")
+                .about("Calculate how much the users of a task agree with each other")
+                .arg(
+                    Arg::with_name("task")
+                        .long("task")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Task to calculate agreement for"),
+                )
+                .arg(
+                    Arg::with_name("metric")
+                        .long("metric")
+                        .takes_value(true)
+                        .help("Metric to calculate agreement for, which defaults to the first metric of the task"),
+                )
+                .arg(
+                    Arg::with_name("group-by")
+                        .long("group-by")
+                        .takes_value(true)
+                        .possible_values(&["source", "gender", "education"])
+                        .help("Calculate agreement separately for each value of this user field"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("side-bias")
+                .about("Estimate whether users favor the sample on the left or on the right")
+                .arg(
+                    Arg::with_name("task")
+                        .long("task")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Task to estimate the side bias for"),
+                )
+                .arg(
+                    Arg::with_name("metric")
+                        .long("metric")
+                        .takes_value(true)
+                        .help("Metric to estimate the side bias for, which defaults to the first metric of the task"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reliability")
+                .about("Calculate the test-retest reliability of users from repeated pairs")
+                .arg(
+                    Arg::with_name("task")
+                        .long("task")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Task to calculate reliability for"),
+                )
+                .arg(
+                    Arg::with_name("metric")
+                        .long("metric")
+                        .takes_value(true)
+                        .help("Metric to calculate reliability for, which defaults to the first metric of the task"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("gold")
+                .about("Score the attention checks of the gold pairs of each user")
+                .arg(
+                    Arg::with_name("task")
+                        .long("task")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Task to score the gold pairs of"),
+                )
+                .arg(
+                    Arg::with_name("metric")
+                        .long("metric")
+                        .takes_value(true)
+                        .help("Metric to score the gold pairs of, which defaults to the first metric of the task"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fit-model")
+                .about("Fit a paired comparison model to weights")
+                .arg(
+                    Arg::with_name("task")
+                        .long("task")
+                        .takes_value(true)
                         .required(true)
-                        .possible_values(&["realistic", "pleasing"])
-                        .help("Type of metric to see stats for"),
+                        .help("Task to fit the model for"),
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .long("token")
+                        .takes_value(true)
+                        .help("User token to fit the model for; all users are pooled if omitted"),
+                )
+                .arg(
+                    Arg::with_name("metric")
+                        .long("metric")
+                        .takes_value(true)
+                        .help("Metric to fit the model for, which defaults to the first metric of the task"),
+                )
+                .arg(
+                    Arg::with_name("model")
+                        .long("model")
+                        .takes_value(true)
+                        .default_value("bradley_terry")
+                        .possible_values(&["bradley_terry", "thurstone"])
+                        .help("Model to fit"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("correlation")
+                .about("Calculate the rank correlation between two rankings")
+                .arg(
+                    Arg::with_name("task")
+                        .long("task")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Task of the rankings"),
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .long("token")
+                        .takes_value(true)
+                        .help("User token of the user rankings, needed if either ranking is a metric of the task"),
+                )
+                .arg(
+                    Arg::with_name("a")
+                        .long("a")
+                        .takes_value(true)
+                        .required(true)
+                        .help(
+                            "First ranking: technical, a metric of the task, or crowd_ followed by a metric",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("b")
+                        .long("b")
+                        .takes_value(true)
+                        .required(true)
+                        .help(
+                            "Second ranking: technical, a metric of the task, or crowd_ followed by a metric",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("save-correlations")
+                .about("Save rank correlations of each user to file")
+                .arg(
+                    Arg::with_name("task")
+                        .long("task")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Task to save correlations for"),
+                )
+                .arg(
+                    Arg::with_name("metric")
+                        .long("metric")
+                        .takes_value(true)
+                        .help("Metric to correlate with the technical and crowd rankings, which defaults to the first metric of the task"),
                 ),
         )
         .subcommand(
@@ -87,9 +441,7 @@ fn main() {
                     Arg::with_name("metric")
                         .long("metric")
                         .takes_value(true)
-                        .required(true)
-                        .possible_values(&["realistic", "pleasing"])
-                        .help("Type of metric to save weights for"),
+                        .help("Metric to save weights for, which defaults to the first metric of the task"),
                 ),
         )
         .subcommand(
@@ -106,9 +458,15 @@ fn main() {
                     Arg::with_name("metric")
                         .long("metric")
                         .takes_value(true)
-                        .required(true)
-                        .possible_values(&["realistic", "pleasing"])
-                        .help("Type of metric to save weights for"),
+                        .help("Metric to save weights for, which defaults to the first metric of the task"),
+                )
+                .arg(
+                    Arg::with_name("method")
+                        .long("method")
+                        .takes_value(true)
+                        .default_value("mean")
+                        .possible_values(&["mean", "eigenvector", "log_least_squares"])
+                        .help("Method used to calculate priorities"),
                 ),
         )
         .subcommand(
@@ -125,14 +483,12 @@ fn main() {
                     Arg::with_name("metric")
                         .long("metric")
                         .takes_value(true)
-                        .required(true)
-                        .possible_values(&["realistic", "pleasing"])
-                        .help("Type of metric to save users for"),
+                        .help("Metric to save users for, which defaults to the first metric of the task"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("save-questionnaires")
-                .about("Save questionnaires to file")
+                .about("Save questionnaires, and answers to the questions of the task, to file")
                 .arg(
                     Arg::with_name("task")
                         .long("task")
@@ -144,73 +500,295 @@ fn main() {
                     Arg::with_name("metric")
                         .long("metric")
                         .takes_value(true)
-                        .required(true)
-                        .possible_values(&["realistic", "pleasing"])
-                        .help("Type of metric to save for"),
+                        .help("Metric to save for, which defaults to the first metric of the task"),
                 ),
         )
         .get_matches();
 
-    if matches.subcommand_matches("server").is_some() {
-        server::run();
+    let config = load_config(&matches);
+
+    if matches.subcommand_name().is_none() {
+        println!("No subcommand used: Exiting.");
+        return;
+    } else if matches.subcommand_matches("server").is_some() {
+        server::run(config);
+        return;
+    } else if let Some(matches) = matches.subcommand_matches("config") {
+        if matches.subcommand_matches("check").is_some() {
+            print_config(&config);
+            if let Err(error) = config.validate() {
+                println!("Invalid config: {}", error);
+                std::process::exit(1);
+            }
+        } else {
+            println!("{}", matches.usage());
+        }
+        return;
+    }
+
+    // The other commands read what the server stored, which a new memory storage does not have
+    if config.db.backend == Backend::Memory {
+        println!("The memory backend can only be used by the server");
+        std::process::exit(1);
+    }
+    let storage = storage::open(&config.db);
+    let storage = &*storage;
+
+    if let Some(matches) = matches.subcommand_matches("migrate") {
+        migrate::run(storage, matches.is_present("dry-run"));
+    } else if let Some(matches) = matches.subcommand_matches("metrics") {
+        let task = matches.value_of("task").unwrap();
+        print_metrics(task, storage);
     } else if let Some(matches) = matches.subcommand_matches("stats") {
         let task = matches.value_of("task").unwrap();
         let token = matches.value_of("token").unwrap();
-        let metric = serde_enum::from_str(matches.value_of("metric").unwrap()).unwrap();
-        let cfg = Config::from_env();
-        if let Err(err) = stats::print_stats(task, token, &metric, &cfg.db) {
+        let metric = get_metric(matches, task, storage);
+        let method = serde_enum::from_str(matches.value_of("method").unwrap()).unwrap();
+        let bootstrap = get_bootstrap_options(matches, Resample::Comparisons);
+        if let Err(err) =
+            stats::print_stats(task, token, &metric, method, bootstrap.as_ref(), storage)
+        {
+            println!("Failed printing stats: {}", err);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("group-stats") {
+        let task = matches.value_of("task").unwrap();
+        let metric = get_metric(matches, task, storage);
+        let method = serde_enum::from_str(matches.value_of("method").unwrap()).unwrap();
+        let aggregation = serde_enum::from_str(matches.value_of("aggregation").unwrap()).unwrap();
+        let filter = UserFilter {
+            source: matches.value_of("source").map(|s| s.to_string()),
+            gender: matches.value_of("gender").map(|s| s.to_string()),
+            education: matches.value_of("education").map(|s| s.to_string()),
+            complete: if matches.is_present("complete") {
+                Some(true)
+            } else {
+                None
+            },
+            include_failed_gold: matches.is_present("include-failed-gold"),
+        };
+        let resample = serde_enum::from_str(matches.value_of("resample").unwrap()).unwrap();
+        let bootstrap = get_bootstrap_options(matches, resample);
+        if let Err(err) =
+            stats::print_group_stats(
+                task,
+                &metric,
+                method,
+                aggregation,
+                &filter,
+                bootstrap.as_ref(),
+                storage,
+            )
+        {
             println!("Failed printing stats: {}", err);
         }
+    } else if let Some(matches) = matches.subcommand_matches("agreement") {
+        let task = matches.value_of("task").unwrap();
+        let metric = get_metric(matches, task, storage);
+        let group_by = matches
+            .value_of("group-by")
+            .map(|group_by| serde_enum::from_str(group_by).unwrap());
+        stats::print_agreement(task, &metric, group_by, storage);
+    } else if let Some(matches) = matches.subcommand_matches("side-bias") {
+        let task = matches.value_of("task").unwrap();
+        let metric = get_metric(matches, task, storage);
+        stats::print_side_bias(task, &metric, storage);
+    } else if let Some(matches) = matches.subcommand_matches("reliability") {
+        let task = matches.value_of("task").unwrap();
+        let metric = get_metric(matches, task, storage);
+        stats::print_reliability(task, &metric, storage);
+    } else if let Some(matches) = matches.subcommand_matches("gold") {
+        let task = matches.value_of("task").unwrap();
+        let metric = get_metric(matches, task, storage);
+        stats::print_gold_scores(task, &metric, storage);
+    } else if let Some(matches) = matches.subcommand_matches("fit-model") {
+        let task = matches.value_of("task").unwrap();
+        let token = matches.value_of("token");
+        let metric = get_metric(matches, task, storage);
+        let model = serde_enum::from_str(matches.value_of("model").unwrap()).unwrap();
+        if let Err(err) = stats::print_model(task, token, &metric, model, storage) {
+            println!("Failed fitting model: {}", err);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("correlation") {
+        let task = matches.value_of("task").unwrap();
+        let token = matches.value_of("token");
+        let a = get_ranking_source(matches, "a", task, storage);
+        let b = get_ranking_source(matches, "b", task, storage);
+        let has_user_ranking = [&a, &b].iter().any(|source| match **source {
+            RankingSource::User(_) => true,
+            _ => false,
+        });
+        if has_user_ranking && token.is_none() {
+            println!("A user ranking needs the user given by --token");
+            std::process::exit(1);
+        }
+        if let Err(err) = stats::print_rank_correlation(task, token, a, b, storage) {
+            println!("Failed calculating correlation: {}", err);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("save-correlations") {
+        let task = matches.value_of("task").unwrap();
+        let metric = get_metric(matches, task, storage);
+        save_correlations(task, metric, &config, storage);
     } else if let Some(matches) = matches.subcommand_matches("save-weights") {
         let task = matches.value_of("task").unwrap();
-        let metric = serde_enum::from_str(matches.value_of("metric").unwrap()).unwrap();
-        let cfg = Config::from_env();
-        save_weights(task, &metric, &cfg.db);
+        let metric = get_metric(matches, task, storage);
+        save_weights(task, &metric, &config, storage);
     } else if let Some(matches) = matches.subcommand_matches("save-criteria-weights") {
         let task = matches.value_of("task").unwrap();
-        let metric = serde_enum::from_str(matches.value_of("metric").unwrap()).unwrap();
-        let cfg = Config::from_env();
-        save_criteria_weights(task, &metric, &cfg.db);
+        let metric = get_metric(matches, task, storage);
+        let method = serde_enum::from_str(matches.value_of("method").unwrap()).unwrap();
+        save_criteria_weights(task, &metric, method, &config, storage);
     } else if let Some(matches) = matches.subcommand_matches("save-users") {
         let task = matches.value_of("task").unwrap();
-        let metric = serde_enum::from_str(matches.value_of("metric").unwrap()).unwrap();
-        let cfg = Config::from_env();
-        save_users(task, &metric, &cfg.db);
+        let metric = get_metric(matches, task, storage);
+        save_users(task, &metric, &config, storage);
     } else if let Some(matches) = matches.subcommand_matches("save-questionnaires") {
         let task = matches.value_of("task").unwrap();
-        let metric = serde_enum::from_str(matches.value_of("metric").unwrap()).unwrap();
-        let cfg = Config::from_env();
-        save_questionnaires(task, &metric, &cfg.db);
-    } else {
-        println!("No subcommand used: Exiting.");
+        let metric = get_metric(matches, task, storage);
+        save_questionnaires(task, &metric, &config, storage);
+    }
+}
+
+/// Bootstrap options given by the flags, if `--bootstrap` is given. Exits when they are invalid.
+fn get_bootstrap_options(matches: &ArgMatches, resample: Resample) -> Option<bootstrap::Options> {
+    let options = matches.value_of("bootstrap").map(|iterations| {
+        bootstrap::Options {
+            iterations: iterations
+                .parse()
+                .expect("Number of bootstrap iterations must be an integer"),
+            seed: matches
+                .value_of("seed")
+                .unwrap()
+                .parse()
+                .expect("Seed must be an integer"),
+            level: matches
+                .value_of("level")
+                .unwrap()
+                .parse()
+                .expect("Confidence level must be a number"),
+            resample: resample,
+        }
+    });
+
+    if let Some(Err(error)) = options.as_ref().map(|options| options.validate()) {
+        println!("{}", error);
+        std::process::exit(1);
+    }
+    options
+}
+
+/// Metric given by `--metric`, or the first metric of the task. Exits when the task does not
+/// collect the metric.
+fn get_metric(matches: &ArgMatches, task: &str, storage: &Storage) -> Metric {
+    let manifest = storage.find_manifest(task);
+    let metric = match matches.value_of("metric") {
+        Some(name) => Metric::new(name).ok(),
+        None => Some(manifest.primary_metric()),
+    };
+
+    match metric {
+        Some(ref metric) if manifest.has_metric(metric) => metric.clone(),
+        _ => exit_with_metrics(task, &manifest),
+    }
+}
+
+/// Ranking given by the argument `name`. Exits when it names a metric the task does not
+/// collect.
+fn get_ranking_source(
+    matches: &ArgMatches,
+    name: &str,
+    task: &str,
+    storage: &Storage,
+) -> RankingSource {
+    let manifest = storage.find_manifest(task);
+    let source = matches.value_of(name).unwrap().parse().ok();
+
+    match source {
+        Some(RankingSource::Technical) => RankingSource::Technical,
+        Some(RankingSource::User(ref metric)) if manifest.has_metric(metric) => {
+            RankingSource::User(metric.clone())
+        }
+        Some(RankingSource::Crowd(ref metric)) if manifest.has_metric(metric) => {
+            RankingSource::Crowd(metric.clone())
+        }
+        _ => exit_with_metrics(task, &manifest),
     }
 }
 
-fn save_weights(task: &str, metric: &Metric, cfg: &cfg::Db) {
-    let db_client = db::connect(cfg);
-    let db = db_client.db(db::NAME);
+/// Configuration of the file given by `--config`, overridden by the environment and then by the
+/// flags given before the subcommand
+fn load_config(matches: &ArgMatches) -> Config {
+    let mut config = match Config::load(matches.value_of("config").map(Path::new)) {
+        Ok(config) => config,
+        Err(error) => {
+            println!("Failed to load config: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(backend) = matches.value_of("db-backend") {
+        config.db.backend = serde_enum::from_str(backend).unwrap();
+    }
+    if let Some(uri) = matches.value_of("db-uri") {
+        config.db.uri = Some(uri.to_string());
+    }
+    if let Some(path) = matches.value_of("db-path") {
+        config.db.path = path.to_string();
+    }
+    if let Some(task_dir) = matches.value_of("task-dir") {
+        config.task_dir = task_dir.to_string();
+    }
+    if let Some(export_dir) = matches.value_of("export-dir") {
+        config.export_dir = export_dir.to_string();
+    }
+
+    config
+}
+
+fn print_config(config: &Config) {
+    println!(
+        "{}",
+        serde_yaml::to_string(&config.redacted()).expect("Failed to serialize config")
+    );
+}
+
+/// Path of a file written by a `save-*` command, in the export directory which is created if
+/// missing
+fn export_path(config: &Config, file_name: &str) -> PathBuf {
+    let dir = Path::new(&config.export_dir);
+    fs::create_dir_all(dir).expect(&format!(
+        "Failed to create export directory '{}'",
+        config.export_dir
+    ));
+    dir.join(file_name)
+}
+
+fn exit_with_metrics(task: &str, manifest: &manifest::Manifest) -> ! {
+    let names: Vec<String> = manifest
+        .metric_names()
+        .iter()
+        .map(|metric| metric.to_string())
+        .collect();
+    println!("Task {} collects the metrics: {}", task, names.join(", "));
+    std::process::exit(1);
+}
 
+fn print_metrics(task: &str, storage: &Storage) {
+    for definition in storage.find_manifest(task).metrics {
+        println!(
+            "{}: {} / {}",
+            definition.name,
+            definition.equal,
+            definition.more
+        );
+    }
+}
+
+fn save_weights(task: &str, metric: &Metric, config: &Config, storage: &Storage) {
     let pairs: Vec<(String, String)> = {
-        let sample_docs: Vec<_> = db.collection(db::COLLECTION_SAMPLE)
-            .find(
-                Some(doc!{ "task": task }),
-                Some(FindOptions {
-                    projection: Some(doc!{
-                        "_id": 1,
-                    }),
-                    ..Default::default()
-                }),
-            )
-            .unwrap()
-            .collect::<Result<_, _>>()
-            .unwrap();
-        let sample_ids: Vec<String> = sample_docs
+        let sample_ids: Vec<String> = storage
+            .find_samples(task, false)
             .into_iter()
-            .map(|doc| {
-                doc.get_object_id("_id")
-                    .expect("Failed deserializing Sample documents")
-                    .to_hex()
-            })
+            .map(|(id, _)| id.to_hex())
             .collect();
         sample_ids
             .iter()
@@ -225,28 +803,18 @@ fn save_weights(task: &str, metric: &Metric, cfg: &cfg::Db) {
             .collect()
     };
 
-    let user_tokens = get_user_tokens(&db_client, task);
-
-    let weights: Vec<Weighting> = {
-        let weight_docs = db.collection(db::COLLECTION_WEIGHT)
-            .find(
-                Some(doc! {
-                    "token": {
-                        "$in": to_bson(&user_tokens).unwrap(),
-                    },
-                    "metric": serde_enum::to_string(metric).unwrap(),
-                }),
-                None,
-            )
-            .expect("Failed quering weights");
-        weight_docs
-            .map(|doc| {
-                let bson = Bson::from(doc.unwrap());
-                let weighting: db::Weighting = from_bson(bson).unwrap();
-                weighting.into()
-            })
-            .collect()
-    };
+    let user_tokens = get_user_tokens(storage, task);
+    let failed_users = stats::find_failed_gold_users(task, metric, storage);
+
+    let weights: Vec<Weighting> = storage
+        .find_weightings(&WeightFilter {
+            tokens: Some(user_tokens),
+            metric: Some(metric.clone()),
+            ..Default::default()
+        })
+        .into_iter()
+        .map(|weighting| weighting.into())
+        .collect();
 
     let incomplete_users = {
         // user -> a -> b, and
@@ -286,17 +854,17 @@ fn save_weights(task: &str, metric: &Metric, cfg: &cfg::Db) {
 
     let weights: Vec<Weighting> = weights
         .into_iter()
-        .filter(|w| !incomplete_users.contains(&w.token))
+        .filter(|w| !incomplete_users.contains(&w.token) && !failed_users.contains(&w.token))
         .collect();
 
-    let sample_names = get_sample_name_map(&db_client, task);
+    let sample_names = get_sample_name_map(storage, task);
 
     let mut writer = csv::WriterBuilder::new()
         .has_headers(true)
-        .from_path("weights.csv")
+        .from_path(export_path(config, "weights.csv"))
         .unwrap();
     writer
-        .write_record(&["user", "a_id", "a_name", "b_id", "b_name", "weight"])
+        .write_record(&["user", "a_id", "a_name", "b_id", "b_name", "weight", "repeat"])
         .unwrap();
 
     for weight in weights {
@@ -308,16 +876,26 @@ fn save_weights(task: &str, metric: &Metric, cfg: &cfg::Db) {
                 &weight.b,
                 &sample_names[&weight.b],
                 &format!("{}", weight.weight),
+                &format!("{}", weight.repeat),
             ])
             .unwrap();
     }
 }
 
-fn save_criteria_weights(task: &str, metric: &Metric, cfg: &cfg::Db) {
-    let db_client = db::connect(cfg);
-
-    let user_tokens = get_user_tokens(&db_client, task);
-    let sample_names = get_sample_name_map(&db_client, task);
+fn save_criteria_weights(
+    task: &str,
+    metric: &Metric,
+    method: PriorityMethod,
+    config: &Config,
+    storage: &Storage,
+) {
+    let failed_users = stats::find_failed_gold_users(task, metric, storage);
+    let user_tokens: Vec<String> = get_user_tokens(storage, task)
+        .into_iter()
+        .filter(|token| !failed_users.contains(token))
+        .collect();
+    let sample_names = get_sample_name_map(storage, task);
+    let task_weights = stats::TaskWeights::load(task, metric, storage);
 
     #[derive(Serialize)]
     struct UserWeight {
@@ -325,14 +903,19 @@ fn save_criteria_weights(task: &str, metric: &Metric, cfg: &cfg::Db) {
         item_id: String,
         item_name: String,
         weight: f32,
+        complete: bool,
     }
 
     let weights: Vec<UserWeight> = user_tokens
         .into_iter()
         .flat_map(|token| {
-            let weights = match stats::calculate_sample_weights(task, &token, metric, &db_client) {
-                Ok(weights) => weights,
-                Err(_) => Vec::new(),
+            let ranking = task_weights.calculate_sample_weights(&token, method, None);
+            let (weights, complete) = match ranking {
+                Ok(ranking) => (ranking.weights, ranking.missing.is_empty()),
+                Err(error) => {
+                    println!("Skipping user {}: {}", token, error);
+                    (Vec::new(), false)
+                }
             };
             let sample_names = &sample_names;
 
@@ -342,6 +925,7 @@ fn save_criteria_weights(task: &str, metric: &Metric, cfg: &cfg::Db) {
                     item_name: sample_names[&w.name].clone(),
                     item_id: w.name,
                     weight: w.weight,
+                    complete: complete,
                 }
             })
         })
@@ -349,7 +933,7 @@ fn save_criteria_weights(task: &str, metric: &Metric, cfg: &cfg::Db) {
 
     let mut writer = csv::WriterBuilder::new()
         .has_headers(true)
-        .from_path("criteria-weights.csv")
+        .from_path(export_path(config, "criteria-weights.csv"))
         .unwrap();
 
     for weight in weights {
@@ -357,84 +941,125 @@ fn save_criteria_weights(task: &str, metric: &Metric, cfg: &cfg::Db) {
     }
 }
 
-fn get_sample_name_map(db_client: &mongodb::Client, task: &str) -> HashMap<String, String> {
-    let sample_docs = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_SAMPLE)
-        .find(
-            Some(doc! {
-                "task": task,
-            }),
-            Some(FindOptions {
-                projection: Some(doc! {
-                    "_id": 1,
-                    "name": 1,
-                }),
-                ..Default::default()
-            }),
-        )
-        .expect("Failed querying samples");
-
-    HashMap::from_iter(sample_docs.map(|doc| {
-        let doc = doc.unwrap();
-        let id = doc.get_object_id("_id").unwrap().to_hex();
-        let name = doc.get_str("name").unwrap().to_string();
-        (id, name)
-    }))
+fn save_correlations(task: &str, metric: Metric, config: &Config, storage: &Storage) {
+    let technical = stats::calculate_technical_weights(task, storage);
+    let crowd = match stats::calculate_group_weights(
+        task,
+        &metric,
+        PriorityMethod::default(),
+        Aggregation::default(),
+        &UserFilter::default(),
+        None,
+        storage,
+    ) {
+        Ok(ranking) => ranking.weights,
+        Err(error) => {
+            println!("Failed calculating crowd ranking: {}", error);
+            return;
+        }
+    };
+
+    #[derive(Serialize)]
+    struct UserCorrelation {
+        user: String,
+        a: String,
+        b: String,
+        samples: usize,
+        kendall_tau: Option<f64>,
+        kendall_p: Option<f64>,
+        spearman_rho: Option<f64>,
+        spearman_p: Option<f64>,
+    }
+
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(true)
+        .from_path(export_path(config, "correlations.csv"))
+        .unwrap();
+
+    let crowd_source = RankingSource::Crowd(metric.clone());
+    let other_metrics: Vec<Metric> = storage.find_manifest(task)
+        .metric_names()
+        .into_iter()
+        .filter(|other| *other != metric)
+        .collect();
+
+    let failed_users = stats::find_failed_gold_users(task, &metric, storage);
+    for token in get_user_tokens(storage, task) {
+        if failed_users.contains(&token) {
+            continue;
+        }
+
+        let source = RankingSource::User(metric.clone());
+        let weights = stats::calculate_source_weights(task, Some(&token), &source, storage);
+        let weights = match weights {
+            Ok(weights) => weights,
+            Err(error) => {
+                println!("Skipping user {}: {}", token, error);
+                continue;
+            }
+        };
+
+        let mut correlations = vec![
+            stats::correlate_weights(&source, &weights, &RankingSource::Technical, &technical),
+            stats::correlate_weights(&source, &weights, &crowd_source, &crowd),
+        ];
+
+        for other in &other_metrics {
+            let other_source = RankingSource::User(other.clone());
+            if let Ok(other_weights) =
+                stats::calculate_source_weights(task, Some(&token), &other_source, storage)
+            {
+                correlations.push(stats::correlate_weights(
+                    &source,
+                    &weights,
+                    &other_source,
+                    &other_weights,
+                ));
+            }
+        }
+
+        for correlation in correlations {
+            writer
+                .serialize(UserCorrelation {
+                    user: token.clone(),
+                    a: correlation.a,
+                    b: correlation.b,
+                    samples: correlation.samples,
+                    kendall_tau: correlation.kendall_tau.map(|c| c.coefficient),
+                    kendall_p: correlation.kendall_tau.map(|c| c.p_value),
+                    spearman_rho: correlation.spearman_rho.map(|c| c.coefficient),
+                    spearman_p: correlation.spearman_rho.map(|c| c.p_value),
+                })
+                .unwrap();
+        }
+    }
 }
 
-fn get_user_tokens(db_client: &mongodb::Client, task: &str) -> Vec<String> {
-    let user_docs = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_USER)
-        .find(
-            Some(doc! {
-                "task": task,
-            }),
-            Some(FindOptions {
-                projection: Some(doc! {
-                    "_id": 0,
-                    "token": 1,
-                }),
-                ..Default::default()
-            }),
-        )
-        .expect("Failed querying users");
-    user_docs
-        .map(|doc| doc.unwrap().get_str("token").unwrap().to_string())
-        .collect()
+fn get_sample_name_map(storage: &Storage, task: &str) -> HashMap<String, String> {
+    HashMap::from_iter(
+        storage
+            .find_samples(task, true)
+            .into_iter()
+            .map(|(id, sample)| (id.to_hex(), sample.name)),
+    )
 }
 
-fn find_completed_users(
-    db_client: &mongodb::Client,
-    task: &str,
-    metric: &Metric,
-) -> HashSet<String> {
-    let user_tokens = get_user_tokens(&db_client, task);
+fn get_user_tokens(storage: &Storage, task: &str) -> Vec<String> {
+    storage
+        .find_users(task)
+        .into_iter()
+        .map(|user| user.token)
+        .collect()
+}
 
-    let db = db_client.db(db::NAME);
+fn find_completed_users(storage: &Storage, task: &str, metric: &Metric) -> HashSet<String> {
+    let user_tokens = get_user_tokens(storage, task);
 
     let pairs: Vec<(String, String)> = {
-        let sample_docs: Vec<_> = db.collection(db::COLLECTION_SAMPLE)
-            .find(
-                Some(doc!{ "task": task }),
-                Some(FindOptions {
-                    projection: Some(doc!{
-                        "_id": 1,
-                    }),
-                    ..Default::default()
-                }),
-            )
-            .unwrap()
-            .collect::<Result<_, _>>()
-            .unwrap();
-        let sample_ids: Vec<String> = sample_docs
+        let sample_ids: Vec<String> = storage
+            .find_samples(task, false)
             .into_iter()
-            .map(|doc| {
-                doc.get_object_id("_id")
-                    .expect("Failed deserializing Sample documents")
-                    .to_hex()
-            })
+            .map(|(id, _)| id.to_hex())
             .collect();
         sample_ids
             .iter()
@@ -449,26 +1074,15 @@ fn find_completed_users(
             .collect()
     };
 
-    let weights: Vec<Weighting> = {
-        let weight_docs = db.collection(db::COLLECTION_WEIGHT)
-            .find(
-                Some(doc! {
-                    "token": {
-                        "$in": to_bson(&user_tokens).unwrap(),
-                    },
-                    "metric": serde_enum::to_string(metric).unwrap(),
-                }),
-                None,
-            )
-            .expect("Failed quering weights");
-        weight_docs
-            .map(|doc| {
-                let bson = Bson::from(doc.unwrap());
-                let weighting: db::Weighting = from_bson(bson).unwrap();
-                weighting.into()
-            })
-            .collect()
-    };
+    let weights: Vec<Weighting> = storage
+        .find_weightings(&WeightFilter {
+            tokens: Some(user_tokens),
+            metric: Some(metric.clone()),
+            ..Default::default()
+        })
+        .into_iter()
+        .map(|weighting| weighting.into())
+        .collect();
 
     // user -> a -> b, and
     // user -> b -> a
@@ -505,28 +1119,20 @@ fn find_completed_users(
     completed_users
 }
 
-fn save_users(task: &str, metric: &Metric, cfg: &cfg::Db) {
-    let db_client = db::connect(cfg);
-
-    let completed_users = find_completed_users(&db_client, task, metric);
+fn save_users(task: &str, metric: &Metric, config: &Config, storage: &Storage) {
+    let completed_users = find_completed_users(storage, task, metric);
+    let task_weights = stats::TaskWeights::load(task, metric, storage);
+    let gold_scores: HashMap<String, stats::GoldScore> =
+        stats::calculate_gold_scores(task, metric, storage)
+            .into_iter()
+            .map(|user| (user.user, user.score))
+            .collect();
 
-    let user_docs = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_USER)
-        .find(
-            Some(doc! {
-                "task": task,
-            }),
-            None,
-        )
-        .expect("Failed querying users");
-    let users: Vec<User> = user_docs
-        .map(|doc| from_bson(Bson::from(doc.unwrap())).unwrap())
-        .collect();
+    let users: Vec<User> = storage.find_users(task);
 
     let mut writer = csv::WriterBuilder::new()
         .has_headers(true)
-        .from_path("users.csv")
+        .from_path(export_path(config, "users.csv"))
         .unwrap();
     writer
         .write_record(&[
@@ -542,11 +1148,28 @@ fn save_users(task: &str, metric: &Metric, cfg: &cfg::Db) {
             "browser.version",
             "complete",
             "post",
+            "consistency_ratio",
+            "circular_triads",
+            "consistence",
+            "gold_checks",
+            "gold_passed",
+            "gold_failed",
         ])
         .unwrap();
 
     for user in users {
         let complete = completed_users.contains(&user.token);
+        let consistency_ratio = if complete {
+            task_weights
+                .calculate_sample_consistency(&user.token)
+                .map(|consistency| format!("{}", consistency.ratio))
+                .unwrap_or_else(|_| "".to_string())
+        } else {
+            "".to_string()
+        };
+        let transitivity = task_weights.calculate_sample_transitivity(&user.token);
+        let gold_score = gold_scores.get(&user.public).cloned();
+
         writer
             .write_record(&[
                 user.public,
@@ -568,13 +1191,22 @@ fn save_users(task: &str, metric: &Metric, cfg: &cfg::Db) {
                     .unwrap_or_else(|| "".to_string()),
                 format!("{}", complete),
                 format!("{}", user.post_questionnaire.is_some()),
+                consistency_ratio,
+                format!("{}", transitivity.circular_triads),
+                transitivity
+                    .consistence
+                    .map(|consistence| format!("{}", consistence))
+                    .unwrap_or_else(|| "".to_string()),
+                format!("{}", gold_score.map_or(0, |score| score.checks)),
+                format!("{}", gold_score.map_or(0, |score| score.passed)),
+                format!("{}", gold_score.map_or(false, |score| score.failed)),
             ])
             .unwrap();
     }
 }
 
-fn save_questionnaires(task: &str, metric: &Metric, cfg: &cfg::Db) {
-    use model::{Likert5, PostQuestionnaire, PreQuestionnaire};
+fn save_questionnaires(task: &str, metric: &Metric, config: &Config, storage: &Storage) {
+    use model::{Answer, Likert5, PostQuestionnaire, PreQuestionnaire};
 
     #[derive(Serialize)]
     struct Questionnaire {
@@ -588,6 +1220,15 @@ fn save_questionnaires(task: &str, metric: &Metric, cfg: &cfg::Db) {
         comments: Option<String>,
     }
 
+    /// Answer to a question defined in the manifest of the task
+    #[derive(Serialize)]
+    struct QuestionAnswer<'a> {
+        user: &'a str,
+        questionnaire: &'a str,
+        question: &'a str,
+        answer: &'a Answer,
+    }
+
     impl From<(String, PreQuestionnaire, Option<PostQuestionnaire>)> for Questionnaire {
         fn from(
             (user, pre, post): (String, PreQuestionnaire, Option<PostQuestionnaire>),
@@ -606,32 +1247,47 @@ fn save_questionnaires(task: &str, metric: &Metric, cfg: &cfg::Db) {
         }
     }
 
-    let db_client = db::connect(cfg);
+    let completed_users = find_completed_users(storage, task, metric);
+    let failed_users = stats::find_failed_gold_users(task, metric, storage);
 
-    let completed_users = find_completed_users(&db_client, task, metric);
-
-    let user_docs = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_USER)
-        .find(
-            Some(doc! {
-                "task": task,
-            }),
-            None,
-        )
-        .expect("Failed querying users");
-    let users: Vec<User> = user_docs
-        .map(|doc| from_bson(Bson::from(doc.unwrap())).unwrap())
-        .collect();
+    let users: Vec<User> = storage.find_users(task);
 
     let mut writer = csv::WriterBuilder::new()
         .has_headers(true)
-        .from_path("questionnaires.csv")
+        .from_path(export_path(config, "questionnaires.csv"))
+        .unwrap();
+    let mut answer_writer = csv::WriterBuilder::new()
+        .has_headers(true)
+        .from_path(export_path(config, "answers.csv"))
         .unwrap();
 
     for user in users {
         let complete = completed_users.contains(&user.token);
-        if complete {
+        if complete && !failed_users.contains(&user.token) {
+            // Scoped to end the borrow of the questionnaires before they are moved below
+            {
+                let answers = user.pre_questionnaire
+                    .iter()
+                    .map(|pre| ("pre", &pre.answers))
+                    .chain(
+                        user.post_questionnaire
+                            .iter()
+                            .map(|post| ("post", &post.answers)),
+                    );
+                for (questionnaire, answers) in answers {
+                    for (question, answer) in answers {
+                        answer_writer
+                            .serialize(QuestionAnswer {
+                                user: &user.public,
+                                questionnaire: questionnaire,
+                                question: question,
+                                answer: answer,
+                            })
+                            .unwrap();
+                    }
+                }
+            }
+
             if let Some(pre) = user.pre_questionnaire {
                 let q = Questionnaire::from((user.public, pre, user.post_questionnaire));
                 writer.serialize(&q).unwrap();
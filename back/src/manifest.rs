@@ -0,0 +1,455 @@
+use chrono::NaiveDateTime;
+use serde_yaml;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use model::{Answer, Metric};
+use scale::Scale;
+
+/// Name of the manifest file in the directory of a task
+pub const FILE_NAME: &str = "task.yml";
+
+/// Description and settings of a task, read from `task.yml` in the directory of the task.
+///
+/// Every setting is optional, and tasks without a manifest use the defaults.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Manifest {
+    /// Title shown to participants
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Metrics that users weight each pair by. Adaptive and balanced designs choose pairs by
+    /// the first one.
+    #[serde(default = "default_metrics")]
+    pub metrics: Vec<MetricDefinition>,
+    /// Weights that users can give each pair
+    #[serde(default)]
+    pub scale: Scale,
+    #[serde(default)]
+    pub design: Design,
+    /// When an adaptive task is finished
+    #[serde(default)]
+    pub stopping: StoppingRule,
+    /// How the samples of a pair are placed on the left and right
+    #[serde(default)]
+    pub sides: Sides,
+    /// Number of pairs that are shown again at the end, with the sides swapped, to measure
+    /// test-retest reliability. Adaptive tasks do not repeat pairs.
+    #[serde(default)]
+    pub repeats: u32,
+    /// Pairs with a known answer that are mixed into the pairs of each user to check their
+    /// attention. Adaptive tasks do not show gold pairs.
+    #[serde(default)]
+    pub gold: Gold,
+    /// Number of pairs each user of a balanced task compares, which defaults to the number of
+    /// samples minus one
+    #[serde(default)]
+    pub pairs_per_user: Option<u32>,
+    /// Questions asked in addition to the built-in questionnaires
+    #[serde(default)]
+    pub questionnaire: Questionnaire,
+    /// Users can not register before this time (UTC)
+    #[serde(default)]
+    pub opens: Option<NaiveDateTime>,
+    /// Users can not register after this time (UTC)
+    #[serde(default)]
+    pub closes: Option<NaiveDateTime>,
+    /// Maximum number of users that can register for the task
+    #[serde(default)]
+    pub max_participants: Option<u32>,
+}
+
+impl Default for Manifest {
+    fn default() -> Manifest {
+        Manifest {
+            title: None,
+            description: None,
+            metrics: default_metrics(),
+            scale: Scale::default(),
+            design: Design::default(),
+            stopping: StoppingRule::default(),
+            sides: Sides::default(),
+            repeats: 0,
+            gold: Gold::default(),
+            pairs_per_user: None,
+            questionnaire: Questionnaire::default(),
+            opens: None,
+            closes: None,
+            max_participants: None,
+        }
+    }
+}
+
+/// What participants are shown of a manifest, leaving out the gold pairs and the settings of
+/// how pairs are chosen and who can take part
+#[derive(Serialize, Clone, Debug)]
+pub struct PublicManifest {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub metrics: Vec<MetricDefinition>,
+    pub scale: Scale,
+    pub design: Design,
+    pub questionnaire: Questionnaire,
+    pub opens: Option<NaiveDateTime>,
+    pub closes: Option<NaiveDateTime>,
+}
+
+impl From<Manifest> for PublicManifest {
+    fn from(manifest: Manifest) -> PublicManifest {
+        PublicManifest {
+            title: manifest.title,
+            description: manifest.description,
+            metrics: manifest.metrics,
+            scale: manifest.scale,
+            design: manifest.design,
+            questionnaire: manifest.questionnaire,
+            opens: manifest.opens,
+            closes: manifest.closes,
+        }
+    }
+}
+
+fn default_metrics() -> Vec<MetricDefinition> {
+    vec![
+        MetricDefinition {
+            name: Metric::new("pleasing").unwrap(),
+            equal: "(dis)pleasing".to_string(),
+            more: "more pleasing".to_string(),
+        },
+    ]
+}
+
+/// A metric that users weight pairs by, with the wording of its slider
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MetricDefinition {
+    pub name: Metric,
+    /// Label of the middle of the slider, where neither sample is preferred
+    pub equal: String,
+    /// Label of both ends of the slider, where one sample is preferred
+    pub more: String,
+}
+
+/// How the pairs a user compares are chosen
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Design {
+    /// Every pair, in random order
+    Exhaustive,
+    /// One pair at a time, chosen from the comparisons made so far
+    Adaptive,
+    /// A fixed number of pairs per user, chosen so that all pairs are covered about equally
+    /// often across users
+    Balanced,
+}
+
+impl Default for Design {
+    fn default() -> Design {
+        Design::Exhaustive
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Sides {
+    /// Flip a coin for each pair
+    Random,
+    /// Place each sample on the left and right about equally often across all users
+    Counterbalanced,
+}
+
+impl Default for Sides {
+    fn default() -> Sides {
+        Sides::Random
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct StoppingRule {
+    /// Stop once no Bradley-Terry score has a larger standard error than this
+    #[serde(default = "default_max_standard_error")]
+    pub max_standard_error: f64,
+    /// Never stop before this many comparisons
+    #[serde(default)]
+    pub min_comparisons: u32,
+    /// Always stop after this many comparisons
+    #[serde(default)]
+    pub max_comparisons: Option<u32>,
+}
+
+impl Default for StoppingRule {
+    fn default() -> StoppingRule {
+        StoppingRule {
+            max_standard_error: default_max_standard_error(),
+            min_comparisons: 0,
+            max_comparisons: None,
+        }
+    }
+}
+
+fn default_max_standard_error() -> f64 {
+    0.5
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Gold {
+    #[serde(default)]
+    pub pairs: Vec<GoldPair>,
+    /// Users that prefer the better sample of fewer gold pairs than this fraction fail the
+    /// attention checks, and are left out of crowd rankings and exports
+    #[serde(default = "default_min_pass_rate")]
+    pub min_pass_rate: f64,
+}
+
+impl Default for Gold {
+    fn default() -> Gold {
+        Gold {
+            pairs: Vec::new(),
+            min_pass_rate: default_min_pass_rate(),
+        }
+    }
+}
+
+fn default_min_pass_rate() -> f64 {
+    1.0
+}
+
+/// A pair of samples, given by name, where the better one should always be preferred. The
+/// worse sample, such as a degraded or blank version of the better one, is only shown in gold
+/// pairs and is left out of the rankings.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GoldPair {
+    pub better: String,
+    pub worse: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Questionnaire {
+    /// Questions asked before the task, together with the built-in pre-questionnaire
+    #[serde(default)]
+    pub pre: Vec<Question>,
+    /// Questions asked after the task, together with the built-in post-questionnaire
+    #[serde(default)]
+    pub post: Vec<Question>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Question {
+    /// Key of the answer, unique within the questionnaire
+    pub id: String,
+    pub text: String,
+    #[serde(default)]
+    pub kind: QuestionKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuestionKind {
+    /// A five-point Likert scale, answered with an integer between -2 and 2
+    Likert,
+    /// A free text answer
+    Text,
+}
+
+impl Default for QuestionKind {
+    fn default() -> QuestionKind {
+        QuestionKind::Likert
+    }
+}
+
+/// Check that every answer is to one of the questions, and of the kind the question asks for.
+/// Questions may be left unanswered.
+pub fn check_answers(
+    questions: &[Question],
+    answers: &HashMap<String, Answer>,
+) -> Result<(), String> {
+    for (id, answer) in answers {
+        let question = match questions.iter().find(|question| question.id == *id) {
+            Some(question) => question,
+            None => return Err(format!("Unknown question '{}'", id)),
+        };
+
+        let matches_kind = match (question.kind, answer) {
+            (QuestionKind::Likert, &Answer::Likert(_)) => true,
+            (QuestionKind::Text, &Answer::Text(_)) => true,
+            _ => false,
+        };
+        if !matches_kind {
+            return Err(format!("Wrong kind of answer to question '{}'", id));
+        }
+    }
+
+    Ok(())
+}
+
+impl Manifest {
+    /// Metric that adaptive and balanced designs choose pairs by
+    pub fn primary_metric(&self) -> Metric {
+        match self.metrics.first() {
+            Some(definition) => definition.name.clone(),
+            None => default_metrics().remove(0).name,
+        }
+    }
+
+    pub fn metric_names(&self) -> Vec<Metric> {
+        self.metrics
+            .iter()
+            .map(|definition| definition.name.clone())
+            .collect()
+    }
+
+    pub fn has_metric(&self, metric: &Metric) -> bool {
+        self.metrics
+            .iter()
+            .any(|definition| definition.name == *metric)
+    }
+
+    /// Whether users can register for the task at `time`
+    pub fn is_open(&self, time: &NaiveDateTime) -> bool {
+        self.opens.map_or(true, |opens| *time >= opens)
+            && self.closes.map_or(true, |closes| *time < closes)
+    }
+
+    /// Problems with the settings that deserializing does not catch, such as duplicate names
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.metrics.is_empty() {
+            errors.push("No metrics".to_string());
+        }
+        let mut names = HashSet::new();
+        for definition in &self.metrics {
+            if !names.insert(definition.name.as_str()) {
+                errors.push(format!("Duplicate metric '{}'", definition.name));
+            }
+        }
+
+        if let Some(error) = self.scale.validate() {
+            errors.push(error);
+        }
+
+        if !(self.stopping.max_standard_error > 0.0) {
+            errors.push("stopping.max_standard_error must be positive".to_string());
+        }
+        if let Some(max_comparisons) = self.stopping.max_comparisons {
+            if max_comparisons < self.stopping.min_comparisons {
+                errors.push(
+                    "stopping.max_comparisons must be at least stopping.min_comparisons"
+                        .to_string(),
+                );
+            }
+        }
+        if self.pairs_per_user == Some(0) {
+            errors.push("pairs_per_user must be positive".to_string());
+        }
+
+        if !(self.gold.min_pass_rate >= 0.0 && self.gold.min_pass_rate <= 1.0) {
+            errors.push("gold.min_pass_rate must be between 0 and 1".to_string());
+        }
+        for pair in &self.gold.pairs {
+            if pair.better == pair.worse {
+                errors.push(format!("Gold pair compares '{}' to itself", pair.better));
+            }
+        }
+
+        for &(stage, questions) in &[
+            ("pre", &self.questionnaire.pre),
+            ("post", &self.questionnaire.post),
+        ] {
+            let mut ids = HashSet::new();
+            for question in questions {
+                if question.id.is_empty() {
+                    errors.push(format!("Question without id in questionnaire.{}", stage));
+                } else if !ids.insert(question.id.as_str()) {
+                    errors.push(format!(
+                        "Duplicate question '{}' in questionnaire.{}",
+                        question.id, stage
+                    ));
+                }
+            }
+        }
+
+        if let (Some(opens), Some(closes)) = (self.opens, self.closes) {
+            if closes <= opens {
+                errors.push("closes must be after opens".to_string());
+            }
+        }
+        if self.max_participants == Some(0) {
+            errors.push("max_participants must be positive".to_string());
+        }
+
+        errors
+    }
+
+    /// Read and validate the manifest in the directory of a task, or use the defaults if it
+    /// has none
+    pub fn read(dir_path: &Path) -> Result<Manifest, io::Error> {
+        let manifest_path = dir_path.join(FILE_NAME);
+        if !manifest_path.is_file() {
+            return Ok(Manifest::default());
+        }
+
+        let file = File::open(&manifest_path)?;
+        let manifest: Manifest = serde_yaml::from_reader(file).map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Could not deserialize manifest '{}': {}",
+                    manifest_path.to_str().unwrap(),
+                    error
+                ),
+            )
+        })?;
+
+        let errors = manifest.validate();
+        if !errors.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Invalid manifest '{}': {}",
+                    manifest_path.to_str().unwrap(),
+                    errors.join("; ")
+                ),
+            ));
+        }
+
+        Ok(manifest)
+    }
+}
+
+#[test]
+fn test_validate() {
+    let manifest: Manifest = serde_yaml::from_str(
+        "
+title: Plants
+metrics:
+  - name: pleasing
+    equal: (dis)pleasing
+    more: more pleasing
+questionnaire:
+  post:
+    - id: favorite
+      text: Which plant did you like the most?
+      kind: text
+opens: 2018-03-01T00:00:00
+closes: 2018-04-01T00:00:00
+max_participants: 100
+",
+    ).unwrap();
+    assert!(manifest.validate().is_empty());
+    assert_eq!(manifest.questionnaire.post[0].kind, QuestionKind::Text);
+    assert!(manifest.is_open(&"2018-03-15T12:00:00".parse().unwrap()));
+    assert!(!manifest.is_open(&"2018-04-01T00:00:00".parse().unwrap()));
+
+    let invalid = Manifest {
+        metrics: vec![manifest.metrics[0].clone(), manifest.metrics[0].clone()],
+        closes: manifest.opens,
+        max_participants: Some(0),
+        ..manifest
+    };
+    assert_eq!(invalid.validate().len(), 3);
+
+    assert!(Manifest::default().validate().is_empty());
+}
@@ -0,0 +1,264 @@
+use bson::oid::ObjectId;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use db::{Presentation, Task, User, Weighting};
+use model::Sample;
+use storage::{Error, Storage, WeightFilter};
+
+#[derive(Default)]
+struct Data {
+    samples: Vec<(ObjectId, Sample)>,
+    tasks: Vec<Task>,
+    users: Vec<User>,
+    weightings: Vec<Weighting>,
+    presentations: Vec<Presentation>,
+    /// Side imbalance of each task and sample
+    side_imbalance: HashMap<(String, String), i32>,
+}
+
+/// Storage that is kept in memory and lost when it is dropped, for tests and trying out tasks
+/// without a database. Keys are unique like in the other backends.
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: Mutex<Data>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    fn lock(&self) -> MutexGuard<Data> {
+        self.data.lock().expect("Storage lock poisoned")
+    }
+}
+
+/// Save samples of a task that all have the same fitness and give their IDs in order
+#[cfg(test)]
+pub fn save_test_samples(storage: &Storage, task: &str, names: &[&str]) -> Vec<ObjectId> {
+    for name in names {
+        let sample = Sample {
+            task: task.to_string(),
+            name: name.to_string(),
+            fitness: 1.0,
+            gold: false,
+        };
+        storage.save_sample(&sample).unwrap();
+    }
+    storage
+        .find_samples(task, false)
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Current weighting of a user by the "pleasing" metric, to override field by field in tests
+#[cfg(test)]
+pub fn test_weighting(token: &str, a: &ObjectId, b: &ObjectId, weight: f32) -> Weighting {
+    use chrono::Utc;
+    use db::SCHEMA_VERSION;
+    use model::Metric;
+
+    Weighting {
+        token: token.to_string(),
+        fullscreen: false,
+        video_size: 720,
+        metric: Metric::new("pleasing").unwrap(),
+        a: a.clone(),
+        b: b.clone(),
+        weight: weight,
+        time: Utc::now().naive_utc(),
+        repeat: false,
+        schema_version: SCHEMA_VERSION,
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn init(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn find_samples(&self, task: &str, include_gold: bool) -> Vec<(ObjectId, Sample)> {
+        self.lock()
+            .samples
+            .iter()
+            .filter(|&&(_, ref sample)| sample.task == task && (include_gold || !sample.gold))
+            .cloned()
+            .collect()
+    }
+
+    fn find_sample(&self, id: &ObjectId) -> Option<Sample> {
+        self.lock()
+            .samples
+            .iter()
+            .find(|&&(ref sample_id, _)| sample_id == id)
+            .map(|&(_, ref sample)| sample.clone())
+    }
+
+    fn find_task_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for &(_, ref sample) in &self.lock().samples {
+            if !names.contains(&sample.task) {
+                names.push(sample.task.clone());
+            }
+        }
+        names
+    }
+
+    fn save_sample(&self, sample: &Sample) -> Result<(), Error> {
+        let mut data = self.lock();
+        if let Some(stored) = data.samples
+            .iter_mut()
+            .find(|stored| stored.1.task == sample.task && stored.1.name == sample.name)
+        {
+            stored.1 = sample.clone();
+            return Ok(());
+        }
+
+        let id = ObjectId::new().map_err(|err| Error::Backend(err.to_string()))?;
+        data.samples.push((id, sample.clone()));
+        Ok(())
+    }
+
+    fn find_task(&self, name: &str) -> Option<Task> {
+        self.lock()
+            .tasks
+            .iter()
+            .find(|task| task.name == name)
+            .cloned()
+    }
+
+    fn save_task(&self, task: &Task) -> Result<(), Error> {
+        let mut data = self.lock();
+        if let Some(stored) = data.tasks.iter_mut().find(|stored| stored.name == task.name) {
+            *stored = task.clone();
+            return Ok(());
+        }
+
+        data.tasks.push(task.clone());
+        Ok(())
+    }
+
+    fn find_user(&self, token: &str) -> Option<User> {
+        self.lock()
+            .users
+            .iter()
+            .find(|user| user.token == token)
+            .cloned()
+    }
+
+    fn find_users(&self, task: &str) -> Vec<User> {
+        self.lock()
+            .users
+            .iter()
+            .filter(|user| user.task == task)
+            .cloned()
+            .collect()
+    }
+
+    fn insert_user(&self, user: &User) -> Result<(), Error> {
+        let mut data = self.lock();
+        if data.users
+            .iter()
+            .any(|stored| stored.token == user.token || stored.public == user.public)
+        {
+            return Err(Error::Duplicate);
+        }
+
+        data.users.push(user.clone());
+        Ok(())
+    }
+
+    fn update_user(&self, user: &User) -> Result<bool, Error> {
+        let mut data = self.lock();
+        match data.users.iter_mut().find(|stored| stored.token == user.token) {
+            Some(stored) => {
+                *stored = user.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn find_weightings(&self, filter: &WeightFilter) -> Vec<Weighting> {
+        self.lock()
+            .weightings
+            .iter()
+            .filter(|weighting| filter.matches(weighting))
+            .cloned()
+            .collect()
+    }
+
+    fn insert_weighting(&self, weighting: &Weighting) -> Result<(), Error> {
+        let mut data = self.lock();
+        if data.weightings.iter().any(|stored| {
+            stored.token == weighting.token && stored.metric == weighting.metric
+                && stored.a == weighting.a && stored.b == weighting.b
+                && stored.repeat == weighting.repeat
+        }) {
+            return Err(Error::Duplicate);
+        }
+
+        data.weightings.push(weighting.clone());
+        Ok(())
+    }
+
+    fn update_weighting(&self, weighting: &Weighting) -> Result<bool, Error> {
+        let mut data = self.lock();
+        let stored = data.weightings.iter_mut().find(|stored| {
+            stored.token == weighting.token && stored.metric == weighting.metric
+                && stored.a == weighting.a && stored.b == weighting.b
+                && stored.repeat == weighting.repeat
+        });
+        match stored {
+            Some(stored) => {
+                *stored = weighting.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn find_user_presentations(&self, token: &str) -> Vec<Presentation> {
+        self.lock()
+            .presentations
+            .iter()
+            .filter(|presentation| presentation.token == token)
+            .cloned()
+            .collect()
+    }
+
+    fn find_side_imbalance(&self, task: &str) -> HashMap<String, i32> {
+        self.lock()
+            .side_imbalance
+            .iter()
+            .filter(|&(&(ref sample_task, _), _)| sample_task == task)
+            .map(|(&(_, ref sample), &imbalance)| (sample.clone(), imbalance))
+            .collect()
+    }
+
+    fn record_presentation(&self, presentation: &Presentation) -> Result<(), Error> {
+        let mut data = self.lock();
+        if data.presentations.iter().any(|stored| {
+            stored.token == presentation.token && stored.a == presentation.a
+                && stored.b == presentation.b
+        }) {
+            return Ok(());
+        }
+
+        data.presentations.push(presentation.clone());
+        for &(sample, side) in &[(&presentation.a, 1), (&presentation.b, -1)] {
+            let key = (presentation.task.clone(), sample.to_hex());
+            *data.side_imbalance.entry(key).or_insert(0) += side;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_memory_storage() {
+    use storage::check_storage;
+
+    check_storage(&MemoryStorage::new());
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use db::{User, Weighting, SCHEMA_VERSION};
+use scale::Scale;
+use storage::{Storage, WeightFilter};
+
+/// Records that were upgraded, or would be in a dry run, and those that could not be
+#[derive(Debug, Default)]
+pub struct Report {
+    pub users: usize,
+    pub weights: usize,
+    pub failures: Vec<String>,
+}
+
+/// Upgrade the users and weights stored with an older schema to `SCHEMA_VERSION`. Records that
+/// are up to date are left alone, so migrating again changes nothing. Nothing is stored in a
+/// dry run.
+pub fn migrate(storage: &Storage, dry_run: bool) -> Report {
+    let mut report = Report::default();
+    // Scale of the task of each user
+    let mut scales: HashMap<String, Scale> = HashMap::new();
+
+    for task in storage.find_task_names() {
+        let scale = storage.find_manifest(&task).scale;
+        for mut user in storage.find_users(&task) {
+            scales.insert(user.token.clone(), scale);
+            if user.schema_version >= SCHEMA_VERSION {
+                continue;
+            }
+
+            upgrade_user(&mut user);
+            if !dry_run {
+                let failure = match storage.update_user(&user) {
+                    Ok(true) => None,
+                    Ok(false) => Some("user not found".to_string()),
+                    Err(error) => Some(error.to_string()),
+                };
+                if let Some(failure) = failure {
+                    report
+                        .failures
+                        .push(format!("User {}: {}", user.token, failure));
+                    continue;
+                }
+            }
+            report.users += 1;
+        }
+    }
+
+    for mut weighting in storage.find_weightings(&WeightFilter::default()) {
+        if weighting.schema_version >= SCHEMA_VERSION {
+            continue;
+        }
+
+        let description = format!(
+            "Weight of user {} on {} and {}",
+            weighting.token, weighting.a, weighting.b
+        );
+        let scale = match scales.get(&weighting.token) {
+            Some(scale) => *scale,
+            None => {
+                report
+                    .failures
+                    .push(format!("{}: user not found", description));
+                continue;
+            }
+        };
+
+        let upgraded = upgrade_weighting(&mut weighting, &scale).and_then(|_| {
+            if dry_run {
+                Ok(())
+            } else {
+                match storage.update_weighting(&weighting) {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err("weight not found".to_string()),
+                    Err(error) => Err(error.to_string()),
+                }
+            }
+        });
+        match upgraded {
+            Ok(()) => report.weights += 1,
+            Err(error) => report.failures.push(format!("{}: {}", description, error)),
+        }
+    }
+
+    report
+}
+
+/// Migrate the storage and print what was done
+pub fn run(storage: &Storage, dry_run: bool) {
+    let report = migrate(storage, dry_run);
+
+    for failure in &report.failures {
+        println!("Failed to upgrade: {}", failure);
+    }
+    println!(
+        "{} {} users and {} weights to schema version {}",
+        if dry_run { "Would upgrade" } else { "Upgraded" },
+        report.users,
+        report.weights,
+        SCHEMA_VERSION
+    );
+}
+
+/// Upgrade a user one version at a time
+fn upgrade_user(user: &mut User) {
+    while user.schema_version < SCHEMA_VERSION {
+        match user.schema_version {
+            // Users registered before seeds were introduced have their pairs ordered by a seed
+            // derived from the token, which is stored so that it stays the same
+            0 => {
+                if user.seed.is_none() {
+                    user.seed = Some(user.seed() as i64);
+                }
+            }
+            version => unreachable!("No upgrade from user schema version {}", version),
+        }
+        user.schema_version += 1;
+    }
+}
+
+/// Upgrade a weight one version at a time, failing if it cannot be converted
+fn upgrade_weighting(weighting: &mut Weighting, scale: &Scale) -> Result<(), String> {
+    while weighting.schema_version < SCHEMA_VERSION {
+        match weighting.schema_version {
+            // Weights were stored as the 7-point slider of the client gave them, with its
+            // rounding errors, before tasks had their own scales. They are mapped onto the
+            // scale of their task, whatever kind it is. Weights that are not on the slider
+            // were stored after scales were introduced, so they are normalized already.
+            0 => {
+                let normalized = Scale::default()
+                    .normalize(weighting.weight)
+                    .unwrap_or(weighting.weight);
+                weighting.weight = scale.map_normalized(normalized)?;
+            }
+            version => unreachable!("No upgrade from weight schema version {}", version),
+        }
+        weighting.schema_version += 1;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_migrate() {
+    use chrono::Utc;
+    use memory::{save_test_samples, test_weighting, MemoryStorage};
+    use model::{Education, Gender, Occupation};
+    use scale::MAX_WEIGHT;
+
+    let storage = MemoryStorage::new();
+    let ids = save_test_samples(&storage, "task", &["a", "b"]);
+
+    let user = User {
+        age: 30,
+        gender: Gender::Other,
+        education: Education::Master,
+        occupation: Occupation::Teaching,
+        token: "0123456789abcdef0123456789abcdef".to_string(),
+        public: "public".to_string(),
+        from: None,
+        source: "url".to_string(),
+        task: "task".to_string(),
+        register_date: Utc::now().naive_utc(),
+        pre_questionnaire: None,
+        post_questionnaire: None,
+        browser: None,
+        assignment: None,
+        seed: None,
+        schema_version: 0,
+    };
+    storage.insert_user(&user).unwrap();
+
+    // The weight of the third point of the old slider, as computed by the client
+    let weighting = Weighting {
+        schema_version: 0,
+        ..test_weighting(&user.token, &ids[0], &ids[1], 1.0 / (1.0 + 8.0 / 3.0 * 3.0) + 1.0e-5)
+    };
+    storage.insert_weighting(&weighting).unwrap();
+
+    let report = migrate(&storage, true);
+    assert_eq!((report.users, report.weights), (1, 1));
+    assert!(report.failures.is_empty());
+    assert_eq!(storage.find_user(&user.token).unwrap().schema_version, 0);
+
+    let report = migrate(&storage, false);
+    assert_eq!((report.users, report.weights), (1, 1));
+    let migrated = storage.find_user(&user.token).unwrap();
+    assert_eq!(migrated.schema_version, SCHEMA_VERSION);
+    assert_eq!(migrated.seed(), user.seed());
+    let migrated = storage.find_weightings(&WeightFilter::default()).pop().unwrap();
+    assert_eq!(migrated.schema_version, SCHEMA_VERSION);
+    assert!((migrated.weight - 1.0 / MAX_WEIGHT).abs() < 1.0e-6);
+
+    let report = migrate(&storage, false);
+    assert_eq!((report.users, report.weights), (0, 0));
+}
@@ -1,9 +1,10 @@
+use std::collections::HashMap;
 use std::i8;
 use std::fmt::{self, Formatter};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::{self, Unexpected, Visitor};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum Gender {
     Male,
@@ -11,11 +12,74 @@ pub enum Gender {
     Other,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum Metric {
-    Realistic,
-    Pleasing,
+/// Name of a metric that users weight pairs by, such as `pleasing`, as defined in the manifest
+/// of a task
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Metric(String);
+
+impl Metric {
+    /// Names consist of lowercase letters, digits and underscores. Names that could be mistaken
+    /// for other rankings, `technical` and those starting with `crowd_`, are not allowed.
+    pub fn new(name: &str) -> Result<Metric, ()> {
+        let valid = !name.is_empty()
+            && name.chars()
+                .all(|c| (c >= 'a' && c <= 'z') || (c >= '0' && c <= '9') || c == '_')
+            && name != "technical" && !name.starts_with("crowd_");
+
+        if valid {
+            Ok(Metric(name.to_string()))
+        } else {
+            Err(())
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for Metric {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+struct MetricVisitor;
+
+impl<'de> Visitor<'de> for MetricVisitor {
+    type Value = Metric;
+
+    fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.write_str("a metric name of lowercase letters, digits and underscores")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Metric, E>
+    where
+        E: de::Error,
+    {
+        match Metric::new(value) {
+            Ok(metric) => Ok(metric),
+            Err(()) => Err(de::Error::invalid_value(Unexpected::Str(value), &self)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Metric {
+    fn deserialize<D>(deserializer: D) -> Result<Metric, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(MetricVisitor)
+    }
 }
 
 #[derive(Deserialize)]
@@ -27,13 +91,19 @@ pub struct Weighting {
     pub a: String,
     pub b: String,
     pub weight: f32,
+    /// Whether the pair is a repeat of a pair weighted earlier, with the sides swapped
+    #[serde(default)]
+    pub repeat: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Sample {
     pub task: String,
     pub name: String,
     pub fitness: f32,
+    /// Whether the sample is the worse sample of a gold pair, which is only shown in gold pairs
+    #[serde(default)]
+    pub gold: bool,
 }
 
 #[derive(Clone, Copy)]
@@ -101,19 +171,33 @@ impl<'de> Deserialize<'de> for Likert5 {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// Answer to a question defined in the manifest of a task
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Answer {
+    Likert(Likert5),
+    Text(String),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PostQuestionnaire {
     pub ranking_agree: Likert5,
     pub disagree_why: Option<String>,
     pub differentiates: Option<String>,
     pub comments: Option<String>,
+    /// Answers to the post-questionnaire questions of the task, by question id
+    #[serde(default)]
+    pub answers: HashMap<String, Answer>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PreQuestionnaire {
     pub plant_work: Likert5,
     pub plant_like: Likert5,
     pub video_game: Likert5,
+    /// Answers to the pre-questionnaire questions of the task, by question id
+    #[serde(default)]
+    pub answers: HashMap<String, Answer>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -122,7 +206,7 @@ pub struct Browser {
     pub version: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum Education {
     None,
@@ -133,7 +217,7 @@ pub enum Education {
     Doctoral,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum Occupation {
     ClericalSupport,
@@ -169,3 +253,14 @@ pub struct User {
     pub pre_questionnaire: Option<PreQuestionnaire>,
     pub browser: Option<Browser>,
 }
+
+#[test]
+fn test_metric() {
+    assert_eq!(Metric::new("pleasing").unwrap().as_str(), "pleasing");
+    assert!(Metric::new("color_2").is_ok());
+    assert!(Metric::new("").is_err());
+    assert!(Metric::new("Pleasing").is_err());
+    assert!(Metric::new("more pleasing").is_err());
+    assert!(Metric::new("technical").is_err());
+    assert!(Metric::new("crowd_pleasing").is_err());
+}
@@ -1,23 +1,29 @@
-use bson::{from_bson, to_bson, Bson};
 use bson::oid::{self, ObjectId};
-use mongodb::{self, ThreadedClient};
-use mongodb::db::ThreadedDatabase;
-use mongodb::coll::options::FindOptions;
-use rand::{thread_rng, Rng};
-use rand::distributions::{IndependentSample, Range};
-use rocket::{Route, State};
+use chrono::Utc;
+use rand::{Rng, SeedableRng, StdRng};
+use base64;
+use rocket::{Outcome, Request, Route, State};
 use rocket::http::{RawStr, Status};
-use rocket::request::FromParam;
+use rocket::request::{self, FromFormValue, FromParam, FromRequest};
 use rocket::response::{status, NamedFile};
 use rocket_contrib::json::Json;
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::path::Path;
 
+use bootstrap::{self, Resample};
+use cfg::Config;
+use choice_model::Model;
 use db;
-use model::{Metric, PostQuestionnaire, PreQuestionnaire, Sample, User, Weighting};
-use stats::{self, SampleWeight};
+use manifest::{self, Design, MetricDefinition, PublicManifest, Sides};
+use model::{Metric, PostQuestionnaire, PreQuestionnaire, User, Weighting};
+use stats::{self, Aggregation, Agreement, GroupBy, GroupRanking, ModelRanking, PriorityMethod,
+            RankCorrelation, Ranking, RankingSource, SampleWeight, TaskSideBias, UserFilter,
+            UserGoldScore, UserReliability, UserTransitivity};
+use selection;
+use scale::Scale;
 use serde_enum;
+use storage::{self, Storage, WeightFilter};
 
 #[derive(Debug)]
 struct RequestError {
@@ -35,7 +41,6 @@ impl RequestError {
         }
     }
 
-    #[allow(dead_code)]
     fn with_status(status: Status, error: &str) -> RequestError {
         RequestError {
             status: status,
@@ -52,7 +57,6 @@ impl RequestError {
         }
     }
 
-    #[allow(dead_code)]
     fn with_description(status: Status, error: &str, description: String) -> RequestError {
         RequestError {
             status: status,
@@ -70,9 +74,78 @@ impl Into<RequestErrorResponse> for RequestError {
     }
 }
 
+/// Guard of the admin routes, which requires the configured admin credentials through HTTP basic
+/// authentication. Everyone is turned away when no credentials are configured.
+struct Admin;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Admin, ()> {
+        let config = match request.guard::<State<Config>>() {
+            Outcome::Success(config) => config,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+
+        let admin = match config.admin {
+            Some(ref admin) => admin,
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+
+        let credentials = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(parse_basic_auth);
+        // Both are compared in full, so that the time taken does not tell which was wrong
+        let authorized = credentials.map_or(false, |(user, password)| {
+            constant_time_eq(user.as_bytes(), admin.user.as_bytes())
+                & constant_time_eq(password.as_bytes(), admin.password.as_bytes())
+        });
+        if authorized {
+            Outcome::Success(Admin)
+        } else {
+            Outcome::Failure((Status::Unauthorized, ()))
+        }
+    }
+}
+
+/// User and password of an `Authorization` header value like `Basic dXNlcjpwYXNzd29yZA==`
+fn parse_basic_auth(header: &str) -> Option<(String, String)> {
+    if !header.starts_with("Basic ") {
+        return None;
+    }
+
+    let decoded = base64::decode(header["Basic ".len()..].trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let separator = decoded.find(':')?;
+
+    Some((
+        decoded[..separator].to_string(),
+        decoded[separator + 1..].to_string(),
+    ))
+}
+
+/// Whether the bytes are equal, taking as long wherever they differ so that the time does not
+/// tell how much of a secret was guessed
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |difference, (x, y)| difference | (x ^ y)) == 0
+}
+
 impl<'r> FromParam<'r> for Metric {
     type Error = &'r RawStr;
 
+    fn from_param(param: &'r RawStr) -> Result<Self, Self::Error> {
+        Metric::new(param).map_err(|_| param)
+    }
+}
+
+impl<'r> FromParam<'r> for Model {
+    type Error = &'r RawStr;
+
     fn from_param(param: &'r RawStr) -> Result<Self, Self::Error> {
         match serde_enum::from_str(param) {
             Ok(value) => Ok(value),
@@ -81,6 +154,61 @@ impl<'r> FromParam<'r> for Metric {
     }
 }
 
+impl<'r> FromParam<'r> for RankingSource {
+    type Error = &'r RawStr;
+
+    fn from_param(param: &'r RawStr) -> Result<Self, Self::Error> {
+        match param.parse() {
+            Ok(value) => Ok(value),
+            Err(_) => Err(param),
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for PriorityMethod {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, Self::Error> {
+        match serde_enum::from_str(form_value) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(form_value),
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for Aggregation {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, Self::Error> {
+        match serde_enum::from_str(form_value) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(form_value),
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for GroupBy {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, Self::Error> {
+        match serde_enum::from_str(form_value) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(form_value),
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for Resample {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, Self::Error> {
+        match serde_enum::from_str(form_value) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(form_value),
+        }
+    }
+}
+
 /// Get all of the routes
 pub fn routes() -> Vec<Route> {
     routes![
@@ -88,11 +216,25 @@ pub fn routes() -> Vec<Route> {
         post_user,
         get_tasks,
         get_task,
+        get_manifest,
         get_criteria_weights,
+        get_criteria_weights_with_options,
+        get_user_model_ranking,
+        get_group_ranking,
+        get_group_ranking_with_options,
+        get_metric_rankings,
+        get_task_model_ranking,
+        get_agreement,
+        get_agreement_with_options,
         get_video,
         post_weight,
         get_sample,
         get_technical_ranking,
+        get_rank_correlation,
+        get_task_transitivity,
+        get_side_bias,
+        get_reliability,
+        get_gold_scores,
         put_pre_questionnaire,
         put_post_questionnaire,
         get_user_task,
@@ -112,46 +254,36 @@ fn index() -> Json {
 #[post("/user", data = "<user>")]
 fn post_user(
     user: Json<User>,
-    db_client: State<mongodb::Client>,
+    storage: State<Box<Storage>>,
 ) -> Result<Json, RequestErrorResponse> {
     let db_user: db::User = user.into_inner().into();
 
-    let sample_res = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_SAMPLE)
-        .find_one(
-            Some(doc! {
-                "task": &db_user.task,
-            }),
-            None,
-        )
-        .expect("Failed retrieving samples");
-
-    if sample_res.is_none() {
+    if storage.find_samples(&db_user.task, false).is_empty() {
         return Err(RequestError::new("Task not found").into());
     }
 
-    let user_bson = to_bson(&db_user).unwrap();
-    let user_doc = user_bson.as_document().unwrap();
-    let insertion = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_USER)
-        .insert_one(user_doc.clone(), None)
-        .expect("Failed inserting new user");
+    let manifest = storage.find_manifest(&db_user.task);
+    if !manifest.is_open(&db_user.register_date) {
+        return Err(RequestError::with_status(Status::Forbidden, "Task is not open").into());
+    }
 
-    if let Some(write_exception) = insertion.write_exception {
-        panic!(
-            "Failed inserting new user: {}",
-            write_exception.description()
-        );
+    if let Some(max_participants) = manifest.max_participants {
+        let participants = storage.find_users(&db_user.task).len();
+        if participants as u64 >= u64::from(max_participants) {
+            return Err(RequestError::with_status(Status::Forbidden, "Task is full").into());
+        }
     }
 
-    if !insertion.acknowledged {
-        panic!("Failed inserting new user: Insertion not acknowleded");
+    if let Some(ref questionnaire) = db_user.pre_questionnaire {
+        if let Err(error) =
+            manifest::check_answers(&manifest.questionnaire.pre, &questionnaire.answers)
+        {
+            return Err(RequestError::new(&error).into());
+        }
     }
 
-    if insertion.inserted_id.is_none() {
-        panic!("Failed inserting new user: Not ID in result");
+    if let Err(error) = storage.insert_user(&db_user) {
+        panic!("Failed inserting new user: {}", error);
     }
 
     Ok(Json(json!({ "token": db_user.token })))
@@ -161,412 +293,764 @@ fn post_user(
 fn put_pre_questionnaire(
     user_token: &RawStr,
     questionnaire: Json<PreQuestionnaire>,
-    db_client: State<mongodb::Client>,
+    storage: State<Box<Storage>>,
 ) -> Result<(), RequestErrorResponse> {
-    let questionnaire_doc = to_bson(&questionnaire.into_inner()).unwrap();
-    let update_res = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_USER)
-        .update_one(
-            doc! {
-                "token": user_token.as_str(),
-            },
-            doc! {
-                "$set": {
-                    "pre_questionnaire": questionnaire_doc,
-                },
-            },
-            None,
-        )
-        .expect("Failed updating user");
-
-    if let Some(write_exception) = update_res.write_exception {
-        panic!("Failed updating user: {}", write_exception.description());
+    let mut user = get_user(user_token.as_str(), &storage)?;
+    let questions = storage.find_manifest(&user.task).questionnaire.pre;
+    if let Err(error) = manifest::check_answers(&questions, &questionnaire.answers) {
+        return Err(RequestError::new(&error).into());
     }
 
-    if !update_res.acknowledged {
-        panic!("Failed updating user: Update not acknowleded");
-    }
-
-    if update_res.matched_count == 0 {
-        return Err(RequestError::not_found("User not found").into());
-    }
-
-    Ok(())
+    user.pre_questionnaire = Some(questionnaire.into_inner());
+    update_user(&user, &storage)
 }
 
 #[put("/user/<user_token>/post", data = "<questionnaire>")]
 fn put_post_questionnaire(
     user_token: &RawStr,
     questionnaire: Json<PostQuestionnaire>,
-    db_client: State<mongodb::Client>,
+    storage: State<Box<Storage>>,
 ) -> Result<(), RequestErrorResponse> {
-    let questionnaire_doc = to_bson(&questionnaire.into_inner()).unwrap();
-    let update_res = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_USER)
-        .update_one(
-            doc! {
-                "token": user_token.as_str(),
-            },
-            doc! {
-                "$set": {
-                    "post_questionnaire": questionnaire_doc,
-                },
-            },
-            None,
-        )
-        .expect("Failed updating user");
-
-    if let Some(write_exception) = update_res.write_exception {
-        panic!("Failed updating user: {}", write_exception.description());
-    }
-
-    if !update_res.acknowledged {
-        panic!("Failed updating user: Update not acknowleded");
-    }
-
-    if update_res.matched_count == 0 {
-        return Err(RequestError::not_found("User not found").into());
+    let mut user = get_user(user_token.as_str(), &storage)?;
+    let questions = storage.find_manifest(&user.task).questionnaire.post;
+    if let Err(error) = manifest::check_answers(&questions, &questionnaire.answers) {
+        return Err(RequestError::new(&error).into());
     }
 
-    Ok(())
+    user.post_questionnaire = Some(questionnaire.into_inner());
+    update_user(&user, &storage)
 }
 
 #[get("/user/<user_token>/task")]
 fn get_user_task(
     user_token: &RawStr,
-    db_client: State<mongodb::Client>,
+    storage: State<Box<Storage>>,
 ) -> Result<Json, RequestErrorResponse> {
-    let user_res = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_USER)
-        .find_one(
-            Some(doc! {
-                "token": user_token.as_str(),
-            }),
-            Some(FindOptions {
-                projection: Some(doc! {
-                    "task": 1,
-                    "_id": 0,
-                }),
-                ..Default::default()
-            }),
-        )
-        .expect("Failed finding user");
-
-    let user_doc = match user_res {
-        Some(doc) => doc,
-        None => return Err(RequestError::not_found("User not found").into()),
-    };
-
-    let task = user_doc
-        .get_str("task")
-        .expect("User did not have 'task' field");
+    let user = get_user(user_token.as_str(), &storage)?;
 
     Ok(Json(json!({
-        "task": task.to_string()
+        "task": user.task
     })))
 }
 
 #[get("/user/<user_token>/public")]
 fn get_user_public(
     user_token: &RawStr,
-    db_client: State<mongodb::Client>,
+    storage: State<Box<Storage>>,
 ) -> Result<Json, RequestErrorResponse> {
-    let user_res = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_USER)
-        .find_one(
-            Some(doc! {
-                "token": user_token.as_str(),
-            }),
-            Some(FindOptions {
-                projection: Some(doc! {
-                    "public": 1,
-                    "_id": 0,
-                }),
-                ..Default::default()
-            }),
-        )
-        .expect("Failed finding user");
-
-    let user_doc = match user_res {
-        Some(doc) => doc,
-        None => return Err(RequestError::not_found("User not found").into()),
-    };
-
-    let public = user_doc
-        .get_str("public")
-        .expect("User did not have 'public' field");
+    let user = get_user(user_token.as_str(), &storage)?;
 
     Ok(Json(json!({
-        "public": public.to_string()
+        "public": user.public
     })))
 }
 
 #[get("/user/<user_token>/source")]
 fn get_user_source(
     user_token: &RawStr,
-    db_client: State<mongodb::Client>,
+    storage: State<Box<Storage>>,
 ) -> Result<Json, RequestErrorResponse> {
-    let user_res = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_USER)
-        .find_one(
-            Some(doc! {
-                "token": user_token.as_str(),
-            }),
-            Some(FindOptions {
-                projection: Some(doc! {
-                    "source": 1,
-                    "_id": 0,
-                }),
-                ..Default::default()
-            }),
-        )
-        .expect("Failed finding user");
-
-    let user_doc = match user_res {
-        Some(doc) => doc,
-        None => return Err(RequestError::not_found("User not found").into()),
-    };
-
-    let source = user_doc
-        .get_str("source")
-        .expect("User did not have 'source' field");
+    let user = get_user(user_token.as_str(), &storage)?;
 
     Ok(Json(json!({
-        "source": source.to_string()
+        "source": user.source
     })))
 }
 
 #[get("/task")]
-fn get_tasks(db_client: State<mongodb::Client>) -> Result<Json<Vec<String>>, RequestErrorResponse> {
-    let task_bsons = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_SAMPLE)
-        .distinct("task", None, None)
-        .expect("Failed retrieving samples");
-
-    let tasks: Vec<String> = task_bsons
-        .iter()
-        .map(|bson_value| bson_value.as_str())
-        .collect::<Option<Vec<&str>>>()
-        .expect("Some task fields were not strings")
-        .into_iter()
-        .map(|s| s.to_string())
-        .collect();
+fn get_tasks(storage: State<Box<Storage>>) -> Result<Json<Vec<String>>, RequestErrorResponse> {
+    Ok(Json(storage.find_task_names()))
+}
 
-    Ok(Json(tasks))
+/// Description of a task for its participants, including its metrics and questionnaires
+#[get("/task/<task>/manifest")]
+fn get_manifest(
+    task: &RawStr,
+    storage: State<Box<Storage>>,
+) -> Result<Json<PublicManifest>, RequestErrorResponse> {
+    match storage.find_task(task.as_str()) {
+        Some(task) => Ok(Json(task.manifest.into())),
+        None => Err(RequestError::not_found("Task not found").into()),
+    }
 }
 
 #[derive(Serialize)]
 struct Pair {
     a: String,
     b: String,
+    /// Whether the pair is a repeat of an earlier pair, with the sides swapped
+    repeat: bool,
+    /// Metrics that the pair still has to be weighted by
+    metrics: Vec<Metric>,
+}
+
+/// Pairs for a user to compare
+#[derive(Serialize)]
+struct TaskPairs {
+    /// With an adaptive design, only the next pair is given and the task must be requested
+    /// again after it has been weighted. The user is finished when there are no pairs.
+    design: Design,
+    /// Metrics of the task, with the wording of their sliders
+    metrics: Vec<MetricDefinition>,
+    scale: Scale,
+    /// Weights that the scale allows, from the strongest preference for the left sample to the
+    /// strongest for the right sample, or `None` if the scale is continuous
+    weights: Option<Vec<f32>>,
+    pairs: Vec<Pair>,
 }
 
 #[get("/task/<user>")]
 fn get_task(
     user: &RawStr,
-    db_client: State<mongodb::Client>,
-) -> Result<Json<Vec<Pair>>, RequestErrorResponse> {
-    let task = get_users_task(user, &db_client)?;
-
-    let weights_cursor = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_WEIGHT)
-        .find(
-            Some(doc! {
-                "token": user.as_str(),
-                "metric": serde_enum::to_string(&Metric::Pleasing).unwrap(),
-            }),
-            Some(FindOptions {
-                projection: Some(doc! {
-                    "_id": 0,
-                    "a": 1,
-                    "b": 1,
-                }),
+    storage: State<Box<Storage>>,
+) -> Result<Json<TaskPairs>, RequestErrorResponse> {
+    let user_doc = get_user(user, &storage)?;
+    let task = user_doc.task.clone();
+    let manifest = storage.find_manifest(&task);
+    let seed = user_doc.seed();
+    let user_presentations = storage.find_user_presentations(user);
+
+    if manifest.design == Design::Adaptive {
+        let metric = manifest.primary_metric();
+        let next_pair =
+            stats::select_next_pair(&task, user, &metric, &manifest.stopping, &storage);
+
+        // The next pair is at the position of the number of pairs weighted so far
+        let position = storage
+            .find_weightings(&WeightFilter {
+                token: Some(user.to_string()),
+                metric: Some(metric.clone()),
                 ..Default::default()
-            }),
-        )
-        .expect("Failed retrieving weights");
-
-    let mut weighted: HashMap<ObjectId, HashSet<ObjectId>> = HashMap::new();
-    for weight_doc in weights_cursor {
-        let weight_doc = weight_doc.expect("Failed retrieving weight");
-        let a = weight_doc.get_object_id("a").unwrap();
-        let b = weight_doc.get_object_id("b").unwrap();
-
-        weighted
-            .entry(a.clone())
-            .or_insert_with(HashSet::new)
-            .insert(b.clone());
-        weighted
-            .entry(b.clone())
-            .or_insert_with(HashSet::new)
-            .insert(a.clone());
-    }
-
-    let sample_cursor = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_SAMPLE)
-        .find(
-            Some(doc! {
-                "task": task.as_str(),
-            }),
-            Some(FindOptions {
-                projection: Some(doc! {
-                    "_id": 1,
-                }),
-                ..Default::default()
-            }),
-        )
-        .expect("Failed retrieving samples");
+            })
+            .len();
+        let mut rng: StdRng = SeedableRng::from_seed(&[seed, position][..]);
+
+        let pairs: Vec<(usize, Pair)> = next_pair
+            .map(|(a, b)| {
+                present_pairs(
+                    &task,
+                    &user_presentations,
+                    manifest.sides,
+                    &mut rng,
+                    vec![(a, b)],
+                    &storage,
+                ).into_iter()
+                    .map(|pair| {
+                        let pair = Pair {
+                            metrics: manifest.metric_names(),
+                            ..pair
+                        };
+                        (position, pair)
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new);
+        record_presentations(&task, user, &user_presentations, &pairs, &storage);
+
+        return Ok(Json(TaskPairs {
+            design: manifest.design,
+            metrics: manifest.metrics,
+            scale: manifest.scale,
+            weights: manifest.scale.weights(),
+            pairs: pairs.into_iter().map(|(_, pair)| pair).collect(),
+        }));
+    }
 
-    let sample_documents: Result<Vec<_>, _> = sample_cursor.collect();
-    let sample_documents = sample_documents.expect("Failed retrieveing sample documents");
+    let weightings = storage.find_weightings(&WeightFilter {
+        token: Some(user.to_string()),
+        ..Default::default()
+    });
 
-    let sample_ids: Vec<&ObjectId> = sample_documents
-        .iter()
-        .map(|doc| doc.get_object_id("_id"))
-        .collect::<Result<_, _>>()
-        .expect("Failed deserializing documents");
-
-    let num_samples = sample_ids.len();
-    let num_pairs = (num_samples * (num_samples - 1)) / 2;
-    let mut pairs = Vec::with_capacity(num_pairs);
-    for (i, id_a) in sample_ids.iter().enumerate() {
-        for id_b in sample_ids.iter().skip(i + 1) {
-            // Only keep pairs that have not been weighted before
-            let needs_measurement = match weighted.get(id_a) {
-                None => true,
-                Some(paired) => !paired.contains(id_b),
-            };
+    // Metric, repeat flag and both orders of each weighted pair
+    let mut weighted: HashSet<(String, bool, String, String)> = HashSet::new();
+    for weighting in weightings {
+        let metric = weighting.metric.as_str().to_string();
+        let a = weighting.a.to_hex();
+        let b = weighting.b.to_hex();
+
+        weighted.insert((metric.clone(), weighting.repeat, a.clone(), b.clone()));
+        weighted.insert((metric, weighting.repeat, b, a));
+    }
 
-            if needs_measurement {
-                pairs.push(Pair {
-                    a: id_a.to_hex(),
-                    b: id_b.to_hex(),
-                });
+    let candidates: Vec<(ObjectId, ObjectId)> = if manifest.design == Design::Balanced {
+        match user_doc.assignment {
+            Some(assignment) => assignment,
+            None => assign_pairs(
+                &task,
+                user,
+                &manifest.primary_metric(),
+                manifest.pairs_per_user,
+                seed,
+                &storage,
+            ),
+        }
+    } else {
+        let sample_ids: Vec<ObjectId> = storage
+            .find_samples(&task, false)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+
+        let mut all_pairs = Vec::new();
+        for (i, id_a) in sample_ids.iter().enumerate() {
+            for id_b in sample_ids.iter().skip(i + 1) {
+                all_pairs.push((id_a.clone(), id_b.clone()));
             }
         }
-    }
+        all_pairs
+    };
 
-    assert!(pairs.len() <= num_pairs);
+    // The order and sides follow from the seed alone, so they are the same on every request
+    let mut ordered: Vec<(String, String)> = candidates
+        .into_iter()
+        .map(|(id_a, id_b)| (id_a.to_hex(), id_b.to_hex()))
+        .collect();
+    ordered.sort();
+    let mut rng: StdRng = SeedableRng::from_seed(&[seed][..]);
+    rng.shuffle(&mut ordered);
 
-    let chance_range = Range::new(0.0, 1.0);
-    let mut rng = thread_rng();
+    // Gold pairs are mixed in at positions that also follow from the seed
+    let gold_pairs = storage.find_gold_pairs(&task);
+    let gold_samples: HashSet<String> = gold_pairs
+        .iter()
+        .map(|&(_, ref worse)| worse.to_hex())
+        .collect();
+    for (better, worse) in gold_pairs {
+        let position = rng.gen_range(0, ordered.len() + 1);
+        ordered.insert(position, (better.to_hex(), worse.to_hex()));
+    }
 
-    // Randomize pairings
-    pairs = pairs
+    let mut pairs = present_pairs(
+        &task,
+        &user_presentations,
+        manifest.sides,
+        &mut rng,
+        ordered,
+        &storage,
+    );
+
+    // The first regular pairs are shown again at the end, with the sides swapped
+    let repeats: Vec<Pair> = pairs
+        .iter()
+        .filter(|pair| !gold_samples.contains(&pair.a) && !gold_samples.contains(&pair.b))
+        .take(manifest.repeats as usize)
+        .map(|pair| Pair {
+            a: pair.b.clone(),
+            b: pair.a.clone(),
+            repeat: true,
+            metrics: Vec::new(),
+        })
+        .collect();
+    pairs.extend(repeats);
+
+    let presented: Vec<(usize, Pair)> = pairs
         .into_iter()
-        .map(|pair| {
-            let chance = chance_range.ind_sample(&mut rng);
-            if chance > 0.5 {
-                Pair {
-                    a: pair.b,
-                    b: pair.a,
-                }
+        .enumerate()
+        // Only keep pairs that still have to be weighted by some metric
+        .filter_map(|(position, pair)| {
+            let metrics: Vec<Metric> = manifest
+                .metric_names()
+                .into_iter()
+                .filter(|metric| {
+                    let key = (
+                        metric.as_str().to_string(),
+                        pair.repeat,
+                        pair.a.clone(),
+                        pair.b.clone(),
+                    );
+                    !weighted.contains(&key)
+                })
+                .collect();
+            if metrics.is_empty() {
+                None
             } else {
-                pair
+                Some((position, Pair { metrics: metrics, ..pair }))
+            }
+        })
+        .collect();
+    record_presentations(&task, user, &user_presentations, &presented, &storage);
+
+    Ok(Json(TaskPairs {
+        design: manifest.design,
+        metrics: manifest.metrics,
+        scale: manifest.scale,
+        weights: manifest.scale.weights(),
+        pairs: presented.into_iter().map(|(_, pair)| pair).collect(),
+    }))
+}
+
+/// Choose which sample of each pair is shown on the left.
+///
+/// When counterbalanced, pairs that have been presented to the user before keep their sides,
+/// and the sides of new pairs even out how often each sample has been shown on each side to
+/// all users of the task.
+fn present_pairs<R: Rng>(
+    task: &str,
+    user_presentations: &[db::Presentation],
+    sides: Sides,
+    rng: &mut R,
+    pairs: Vec<(String, String)>,
+    storage: &Storage,
+) -> Vec<Pair> {
+    if sides == Sides::Random {
+        return pairs
+            .into_iter()
+            .map(|(a, b)| present_pair(rng, a, b))
+            .collect();
+    }
+
+    let mut imbalance = storage.find_side_imbalance(task);
+    let mut presented: HashMap<(String, String), (String, String)> = HashMap::new();
+    // Repeats are presented with the sides swapped, so they do not keep the sides of a pair
+    for presentation in user_presentations.iter().filter(|presentation| !presentation.repeat) {
+        let a = presentation.a.to_hex();
+        let b = presentation.b.to_hex();
+
+        presented.insert((a.clone(), b.clone()), (a.clone(), b.clone()));
+        presented.insert((b.clone(), a.clone()), (a, b));
+    }
+
+    pairs
+        .into_iter()
+        .map(|(a, b)| {
+            let (a, b) = match presented.get(&(a.clone(), b.clone())) {
+                Some(&(ref left, ref right)) => (left.clone(), right.clone()),
+                None => selection::orient_counterbalanced(&mut imbalance, a, b, rng),
+            };
+            Pair {
+                a: a,
+                b: b,
+                repeat: false,
+                metrics: Vec::new(),
             }
         })
+        .collect()
+}
+
+/// Randomly choose which sample of a pair is shown on the left
+fn present_pair<R: Rng>(rng: &mut R, a: String, b: String) -> Pair {
+    let (a, b) = if rng.gen() { (b, a) } else { (a, b) };
+    Pair {
+        a: a,
+        b: b,
+        repeat: false,
+        metrics: Vec::new(),
+    }
+}
+
+/// Record the position and sides of pairs served to a user, keeping the first record of each.
+/// Only pairs that are not among the user's presentations are written.
+fn record_presentations(
+    task: &str,
+    user_token: &str,
+    user_presentations: &[db::Presentation],
+    pairs: &[(usize, Pair)],
+    storage: &Storage,
+) {
+    let recorded: HashSet<(String, String)> = user_presentations
+        .iter()
+        .map(|presentation| (presentation.a.to_hex(), presentation.b.to_hex()))
         .collect();
 
-    // Randomize pair ordering
-    rng.shuffle(&mut pairs);
+    for &(position, ref pair) in pairs {
+        if recorded.contains(&(pair.a.clone(), pair.b.clone())) {
+            continue;
+        }
 
-    Ok(Json(pairs))
+        let presentation = db::Presentation {
+            token: user_token.to_string(),
+            task: task.to_string(),
+            a: ObjectId::with_string(&pair.a).unwrap(),
+            b: ObjectId::with_string(&pair.b).unwrap(),
+            position: position as i32,
+            repeat: pair.repeat,
+            time: Utc::now().naive_utc(),
+        };
+
+        storage
+            .record_presentation(&presentation)
+            .expect("Failed recording presentation");
+    }
 }
 
-#[get("/ranking/<user>/<metric>")]
+#[derive(FromForm)]
+struct RankingOptions {
+    method: Option<PriorityMethod>,
+    /// Number of bootstrap iterations, if any
+    bootstrap: Option<usize>,
+    seed: Option<usize>,
+    level: Option<f32>,
+}
+
+#[get("/ranking/<user>/<metric>?<options>", rank = 1)]
+fn get_criteria_weights_with_options(
+    user: &RawStr,
+    metric: Metric,
+    options: RankingOptions,
+    storage: State<Box<Storage>>,
+) -> Result<Json<Ranking>, RequestErrorResponse> {
+    let bootstrap = make_bootstrap_options(
+        options.bootstrap,
+        options.seed,
+        options.level,
+        Resample::Comparisons,
+    )?;
+
+    calculate_ranking(
+        user,
+        &metric,
+        options.method.unwrap_or_default(),
+        bootstrap.as_ref(),
+        &storage,
+    )
+}
+
+#[get("/ranking/<user>/<metric>", rank = 2)]
 fn get_criteria_weights(
     user: &RawStr,
     metric: Metric,
-    db_client: State<mongodb::Client>,
-) -> Result<Json<Vec<SampleWeight>>, RequestErrorResponse> {
-    let task = get_users_task(user, &db_client)?;
-    let weights = stats::calculate_sample_weights(&task, user, &metric, &db_client);
+    storage: State<Box<Storage>>,
+) -> Result<Json<Ranking>, RequestErrorResponse> {
+    calculate_ranking(user, &metric, PriorityMethod::default(), None, &storage)
+}
 
-    if let Ok(weights) = weights {
-        Ok(Json(weights))
-    } else {
-        Err(RequestError::not_found("Missing weights").into())
+fn calculate_ranking(
+    user: &str,
+    metric: &Metric,
+    method: PriorityMethod,
+    bootstrap: Option<&bootstrap::Options>,
+    storage: &Storage,
+) -> Result<Json<Ranking>, RequestErrorResponse> {
+    let task = get_users_task(user, storage)?;
+    check_metric(&task, metric, storage)?;
+    let ranking =
+        stats::calculate_sample_weights(&task, user, metric, method, bootstrap, storage);
+
+    match ranking {
+        Ok(ranking) => Ok(Json(ranking)),
+        Err(error) => Err(RequestError::with_description(
+            Status::NotFound,
+            "Missing weights",
+            error.to_string(),
+        ).into()),
     }
 }
 
-#[get("/task/<task>/ranking/technical")]
-fn get_technical_ranking(
+#[derive(FromForm)]
+struct GroupRankingOptions {
+    method: Option<PriorityMethod>,
+    aggregation: Option<Aggregation>,
+    source: Option<String>,
+    gender: Option<String>,
+    education: Option<String>,
+    complete: Option<bool>,
+    include_failed_gold: Option<bool>,
+    /// Number of bootstrap iterations, if any
+    bootstrap: Option<usize>,
+    seed: Option<usize>,
+    level: Option<f32>,
+    resample: Option<Resample>,
+}
+
+#[get("/task/<task>/ranking/<metric>?<options>", rank = 2)]
+fn get_group_ranking_with_options(
     task: &RawStr,
-    db_client: State<mongodb::Client>,
-) -> Result<Json<Vec<SampleWeight>>, RequestErrorResponse> {
-    let sample_cursor = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_SAMPLE)
-        .find(
-            Some(doc! {
-                "task": task.as_str(),
-            }),
-            None,
-        )
-        .expect("Failed retrieving samples");
+    metric: Metric,
+    options: GroupRankingOptions,
+    storage: State<Box<Storage>>,
+) -> Result<Json<GroupRanking>, RequestErrorResponse> {
+    let filter = UserFilter {
+        source: options.source,
+        gender: options.gender,
+        education: options.education,
+        complete: options.complete,
+        include_failed_gold: options.include_failed_gold.unwrap_or(false),
+    };
+    let bootstrap = make_bootstrap_options(
+        options.bootstrap,
+        options.seed,
+        options.level,
+        options.resample.unwrap_or(Resample::Users),
+    )?;
+
+    calculate_group_ranking(
+        task,
+        &metric,
+        options.method.unwrap_or_default(),
+        options.aggregation.unwrap_or_default(),
+        &filter,
+        bootstrap.as_ref(),
+        &storage,
+    )
+}
 
-    let documents: Result<Vec<_>, _> = sample_cursor.collect();
-    let documents = documents.expect("Failed retrieveing sample documents");
+#[get("/task/<task>/ranking/<metric>", rank = 3)]
+fn get_group_ranking(
+    task: &RawStr,
+    metric: Metric,
+    storage: State<Box<Storage>>,
+) -> Result<Json<GroupRanking>, RequestErrorResponse> {
+    calculate_group_ranking(
+        task,
+        &metric,
+        PriorityMethod::default(),
+        Aggregation::default(),
+        &UserFilter::default(),
+        None,
+        &storage,
+    )
+}
 
-    let samples: Vec<(String, Sample)> = documents
-        .into_iter()
-        .map(|doc| {
-            (
-                doc.get_object_id("_id").unwrap().to_hex(),
-                from_bson(Bson::from(doc)).unwrap(),
-            )
-        })
-        .collect();
+#[derive(Serialize)]
+struct MetricRanking {
+    metric: Metric,
+    /// Missing when no user has weighted by the metric yet
+    ranking: Option<GroupRanking>,
+}
 
-    let mut weights: Vec<_> = samples
+/// Ranking of a task by each of the metrics it collects
+#[get("/task/<task>/ranking")]
+fn get_metric_rankings(
+    task: &RawStr,
+    storage: State<Box<Storage>>,
+) -> Json<Vec<MetricRanking>> {
+    let rankings = storage.find_manifest(task)
+        .metric_names()
         .into_iter()
-        .map(|(id, sample)| {
-            SampleWeight {
-                name: id,
-                weight: sample.fitness,
+        .map(|metric| {
+            let ranking = stats::calculate_group_weights(
+                task,
+                &metric,
+                PriorityMethod::default(),
+                Aggregation::default(),
+                &UserFilter::default(),
+                None,
+                &storage,
+            );
+            MetricRanking {
+                metric: metric,
+                ranking: ranking.ok(),
             }
         })
         .collect();
 
-    let normalizer = 1.0 / weights.iter().map(|weight| weight.weight).sum::<f32>();
-    for weight in &mut weights {
-        weight.weight *= normalizer;
+    Json(rankings)
+}
+
+fn calculate_group_ranking(
+    task: &str,
+    metric: &Metric,
+    method: PriorityMethod,
+    aggregation: Aggregation,
+    filter: &UserFilter,
+    bootstrap: Option<&bootstrap::Options>,
+    storage: &Storage,
+) -> Result<Json<GroupRanking>, RequestErrorResponse> {
+    check_metric(task, metric, storage)?;
+
+    let ranking = stats::calculate_group_weights(
+        task,
+        metric,
+        method,
+        aggregation,
+        filter,
+        bootstrap,
+        storage,
+    );
+
+    match ranking {
+        Ok(ranking) => Ok(Json(ranking)),
+        Err(error) => Err(RequestError::with_description(
+            Status::NotFound,
+            "Missing weights",
+            error.to_string(),
+        ).into()),
+    }
+}
+
+fn make_bootstrap_options(
+    iterations: Option<usize>,
+    seed: Option<usize>,
+    level: Option<f32>,
+    resample: Resample,
+) -> Result<Option<bootstrap::Options>, RequestErrorResponse> {
+    let iterations = match iterations {
+        Some(iterations) => iterations,
+        None => return Ok(None),
+    };
+
+    let options = bootstrap::Options {
+        iterations: iterations,
+        seed: seed.unwrap_or(0),
+        level: level.unwrap_or(bootstrap::DEFAULT_LEVEL),
+        resample: resample,
+    };
+    match options.validate() {
+        Ok(()) => Ok(Some(options)),
+        Err(error) => Err(RequestError::new(&error).into()),
+    }
+}
+
+#[derive(FromForm)]
+struct AgreementOptions {
+    group_by: Option<GroupBy>,
+}
+
+#[get("/task/<task>/agreement/<metric>?<options>", rank = 1)]
+fn get_agreement_with_options(
+    task: &RawStr,
+    metric: Metric,
+    options: AgreementOptions,
+    storage: State<Box<Storage>>,
+) -> Result<Json<Vec<Agreement>>, RequestErrorResponse> {
+    check_metric(task, &metric, &storage)?;
+    Ok(Json(stats::calculate_agreement(
+        task,
+        &metric,
+        options.group_by,
+        &storage,
+    )))
+}
+
+#[get("/task/<task>/agreement/<metric>", rank = 2)]
+fn get_agreement(
+    task: &RawStr,
+    metric: Metric,
+    storage: State<Box<Storage>>,
+) -> Result<Json<Vec<Agreement>>, RequestErrorResponse> {
+    check_metric(task, &metric, &storage)?;
+    Ok(Json(stats::calculate_agreement(task, &metric, None, &storage)))
+}
+
+#[get("/ranking/<user>/<metric>/model/<model>")]
+fn get_user_model_ranking(
+    user: &RawStr,
+    metric: Metric,
+    model: Model,
+    storage: State<Box<Storage>>,
+) -> Result<Json<ModelRanking>, RequestErrorResponse> {
+    let task = get_users_task(user, &storage)?;
+    check_metric(&task, &metric, &storage)?;
+    let ranking = stats::fit_user_model(&task, user, &metric, model, &storage);
+
+    match ranking {
+        Ok(ranking) => Ok(Json(ranking)),
+        Err(error) => Err(RequestError::with_description(
+            Status::NotFound,
+            "Missing weights",
+            error.to_string(),
+        ).into()),
+    }
+}
+
+#[get("/task/<task>/ranking/<metric>/model/<model>")]
+fn get_task_model_ranking(
+    task: &RawStr,
+    metric: Metric,
+    model: Model,
+    storage: State<Box<Storage>>,
+) -> Result<Json<ModelRanking>, RequestErrorResponse> {
+    check_metric(task, &metric, &storage)?;
+    let ranking = stats::fit_task_model(task, &metric, model, &storage);
+
+    match ranking {
+        Ok(ranking) => Ok(Json(ranking)),
+        Err(error) => Err(RequestError::with_description(
+            Status::NotFound,
+            "Missing weights",
+            error.to_string(),
+        ).into()),
     }
-    weights.sort_by(|a, b| b.weight.partial_cmp(&a.weight).unwrap());
+}
 
-    Ok(Json(weights))
+#[get("/task/<task>/ranking/technical")]
+fn get_technical_ranking(
+    task: &RawStr,
+    storage: State<Box<Storage>>,
+) -> Result<Json<Vec<SampleWeight>>, RequestErrorResponse> {
+    Ok(Json(stats::calculate_technical_weights(task, &storage)))
+}
+
+#[get("/correlation/<user>/<a>/<b>")]
+fn get_rank_correlation(
+    user: &RawStr,
+    a: RankingSource,
+    b: RankingSource,
+    storage: State<Box<Storage>>,
+) -> Result<Json<RankCorrelation>, RequestErrorResponse> {
+    let task = get_users_task(user, &storage)?;
+    let correlation =
+        stats::calculate_rank_correlation(&task, Some(user.as_str()), a, b, &storage);
+
+    match correlation {
+        Ok(correlation) => Ok(Json(correlation)),
+        Err(error) => Err(RequestError::with_description(
+            Status::NotFound,
+            "Missing weights",
+            error.to_string(),
+        ).into()),
+    }
+}
+
+#[get("/admin/task/<task>/triads/<metric>")]
+fn get_task_transitivity(
+    task: &RawStr,
+    metric: Metric,
+    storage: State<Box<Storage>>,
+    _admin: Admin,
+) -> Result<Json<Vec<UserTransitivity>>, RequestErrorResponse> {
+    check_metric(task, &metric, &storage)?;
+    Ok(Json(stats::calculate_task_transitivity(task, &metric, &storage)))
+}
+
+#[get("/admin/task/<task>/side-bias/<metric>")]
+fn get_side_bias(
+    task: &RawStr,
+    metric: Metric,
+    storage: State<Box<Storage>>,
+    _admin: Admin,
+) -> Result<Json<TaskSideBias>, RequestErrorResponse> {
+    check_metric(task, &metric, &storage)?;
+    Ok(Json(stats::calculate_side_bias(task, &metric, &storage)))
+}
+
+#[get("/admin/task/<task>/reliability/<metric>")]
+fn get_reliability(
+    task: &RawStr,
+    metric: Metric,
+    storage: State<Box<Storage>>,
+    _admin: Admin,
+) -> Result<Json<Vec<UserReliability>>, RequestErrorResponse> {
+    check_metric(task, &metric, &storage)?;
+    Ok(Json(stats::calculate_reliability(task, &metric, &storage)))
+}
+
+#[get("/admin/task/<task>/gold/<metric>")]
+fn get_gold_scores(
+    task: &RawStr,
+    metric: Metric,
+    storage: State<Box<Storage>>,
+    _admin: Admin,
+) -> Result<Json<Vec<UserGoldScore>>, RequestErrorResponse> {
+    check_metric(task, &metric, &storage)?;
+    Ok(Json(stats::calculate_gold_scores(task, &metric, &storage)))
 }
 
 #[get("/video/<id>/<ext>")]
-fn get_video(id: &RawStr, ext: &RawStr, db_client: State<mongodb::Client>) -> Option<NamedFile> {
+fn get_video(
+    id: &RawStr,
+    ext: &RawStr,
+    storage: State<Box<Storage>>,
+    config: State<Config>,
+) -> Option<NamedFile> {
     let object_id = match ObjectId::with_string(id) {
         Ok(object_id) => object_id,
         Err(_) => return None,
     };
 
-    let doc = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_SAMPLE)
-        .find_one(
-            Some(doc! {
-                "_id": object_id,
-            }),
-            None,
-        )
-        .expect("Failed retrieving samples");
-
-    if let Some(doc) = doc {
-        let sample: Sample = from_bson(Bson::from(doc)).expect("Failed deserializing doc");
+    if let Some(sample) = storage.find_sample(&object_id) {
         let filename = format!("{}.{}", sample.name, ext);
-        let path = Path::new("task/").join(sample.task).join(filename);
+        let path = Path::new(&config.task_dir).join(sample.task).join(filename);
         println!("{}", path.to_str().unwrap());
         NamedFile::open(path).ok()
     } else {
@@ -577,67 +1061,84 @@ fn get_video(id: &RawStr, ext: &RawStr, db_client: State<mongodb::Client>) -> Op
 #[post("/weight", data = "<weighting>")]
 fn post_weight(
     weighting: Json<Weighting>,
-    db_client: State<mongodb::Client>,
+    storage: State<Box<Storage>>,
 ) -> Result<Json, RequestErrorResponse> {
-    let db = db_client.db(db::NAME);
-
-    if db.collection(db::COLLECTION_USER)
-        .find_one(Some(doc!{ "token": &weighting.token }), None)
-        .expect("Failed looking up user")
-        .is_none()
-    {
-        return Err(RequestError::new("User not registered").into());
-    }
-
-    if db.collection(db::COLLECTION_WEIGHT)
-        .find_one(
-            Some(doc!{
-                "token": &weighting.token,
-                "metric": serde_enum::to_string(&weighting.metric).unwrap(),
-                "a": ObjectId::with_string(&weighting.a).unwrap(),
-                "b": ObjectId::with_string(&weighting.b).unwrap(),
-            }),
-            None,
-        )
-        .expect("Failed looking up weight")
-        .is_some()
-    {
-        return Err(RequestError::new("Weight already registered").into());
+    let user = match storage.find_user(&weighting.token) {
+        Some(user) => user,
+        None => return Err(RequestError::new("User not registered").into()),
+    };
+
+    let manifest = storage.find_manifest(&user.task);
+    if !manifest.has_metric(&weighting.metric) {
+        return Err(RequestError::new("Metric not collected by task").into());
     }
 
-    let db_weighting: db::Weighting = weighting.into_inner().into();
-    let weight_bson = to_bson(&db_weighting).unwrap();
-    let weight_doc = weight_bson.as_document().unwrap();
+    let mut weighting = weighting.into_inner();
+    weighting.weight = match manifest.scale.normalize(weighting.weight) {
+        Ok(weight) => weight,
+        Err(error) => return Err(RequestError::new(&error).into()),
+    };
 
-    let insertion = db.collection(db::COLLECTION_WEIGHT)
-        .insert_one(weight_doc.clone(), None)
-        .expect("Failed inserting new weight");
+    let a = ObjectId::with_string(&weighting.a).unwrap();
+    let b = ObjectId::with_string(&weighting.b).unwrap();
 
-    if let Some(write_exception) = insertion.write_exception {
-        panic!(
-            "Failed inserting new weight: {}",
-            write_exception.description()
-        );
+    if weighting.repeat {
+        let presented = storage
+            .find_user_presentations(&weighting.token)
+            .iter()
+            .any(|presentation| presentation.repeat && presentation.a == a && presentation.b == b);
+        if !presented {
+            return Err(RequestError::new("Pair has not been presented as a repeat").into());
+        }
+
+        let weighted = storage.find_weightings(&WeightFilter {
+            token: Some(weighting.token.clone()),
+            metric: Some(weighting.metric.clone()),
+            pair: Some((b.clone(), a.clone())),
+            repeat: Some(false),
+            ..Default::default()
+        });
+        if weighted.is_empty() {
+            return Err(RequestError::new("Repeated pair has not been weighted before").into());
+        }
     }
 
-    if !insertion.acknowledged {
-        panic!("Failed inserting new weight: Insertion not acknowleded");
+    // Weights stored before repeats were introduced have no repeat flag, which the unique
+    // index of MongoDB does not take as false, so duplicates of them are looked up first
+    let duplicates = storage.find_weightings(&WeightFilter {
+        token: Some(weighting.token.clone()),
+        metric: Some(weighting.metric.clone()),
+        pair: Some((a, b)),
+        repeat: Some(weighting.repeat),
+        ..Default::default()
+    });
+    if !duplicates.is_empty() {
+        return Err(RequestError::new("Weight already registered").into());
     }
 
-    if insertion.inserted_id.is_none() {
-        panic!("Failed inserting new weight: Not ID in result");
+    let db_weighting: db::Weighting = weighting.into();
+    match storage.insert_weighting(&db_weighting) {
+        Ok(()) => Ok(Json(json!({}))),
+        Err(storage::Error::Duplicate) => {
+            Err(RequestError::new("Weight already registered").into())
+        }
+        Err(error) => panic!("Failed inserting new weight: {}", error),
     }
+}
 
-    Ok(Json(json!({})))
+/// A sample as served to users, which does not tell whether it belongs to a gold pair
+#[derive(Serialize)]
+struct PublicSample {
+    task: String,
+    name: String,
+    fitness: f32,
 }
 
 #[get("/sample/<id>")]
 fn get_sample(
     id: &RawStr,
-    db_client: State<mongodb::Client>,
-) -> Result<Json<Sample>, RequestErrorResponse> {
-    let db = db_client.db(db::NAME);
-
+    storage: State<Box<Storage>>,
+) -> Result<Json<PublicSample>, RequestErrorResponse> {
     let object_id = match ObjectId::with_string(id) {
         Ok(object_id) => object_id,
         Err(error) => match error {
@@ -648,44 +1149,179 @@ fn get_sample(
         },
     };
 
-    let sample_res = db.collection(db::COLLECTION_SAMPLE)
-        .find_one(
-            Some(doc!{
-                "_id": object_id,
-            }),
-            None,
-        )
-        .expect("Failed looking up sample");
+    match storage.find_sample(&object_id) {
+        Some(sample) => Ok(Json(PublicSample {
+            task: sample.task,
+            name: sample.name,
+            fitness: sample.fitness,
+        })),
+        None => Err(RequestError::not_found("Sample not found").into()),
+    }
+}
 
-    if let Some(sample_doc) = sample_res {
-        let sample = from_bson(Bson::from(sample_doc)).expect("Failed deserializing Sample");
-        Ok(Json(sample))
+/// Assign pairs to a user of a balanced task and store them with the user
+fn assign_pairs(
+    task: &str,
+    user_token: &str,
+    metric: &Metric,
+    pairs_per_user: Option<u32>,
+    seed: usize,
+    storage: &Storage,
+) -> Vec<(ObjectId, ObjectId)> {
+    let assignment =
+        stats::assign_balanced_pairs(task, user_token, metric, pairs_per_user, seed, storage);
+
+    if let Some(mut user) = storage.find_user(user_token) {
+        user.assignment = Some(assignment.clone());
+        storage.update_user(&user).expect("Failed updating user");
+    }
+
+    assignment
+}
+
+/// Fail with 404 unless the metric is one of the metrics of the task
+fn check_metric(
+    task: &str,
+    metric: &Metric,
+    storage: &Storage,
+) -> Result<(), RequestErrorResponse> {
+    if storage.find_manifest(task).has_metric(metric) {
+        Ok(())
     } else {
-        return Err(RequestError::not_found("Sample not found").into());
+        Err(RequestError::not_found("Metric not collected by task").into())
     }
 }
 
 fn get_users_task(
     user_token: &str,
-    db_client: &mongodb::Client,
+    storage: &Storage,
 ) -> Result<String, RequestErrorResponse> {
-    let user_res = db_client
-        .db(db::NAME)
-        .collection(db::COLLECTION_USER)
-        .find_one(
-            Some(doc! {
-                "token": user_token,
-            }),
-            None,
-        )
-        .expect("Failed retrieving user");
+    get_user(user_token, storage).map(|user| user.task)
+}
 
-    match user_res {
-        Some(user_doc) => {
-            let user: db::User =
-                from_bson(Bson::from(user_doc)).expect("Failed deserializing User");
-            Ok(user.task)
-        }
+fn get_user(
+    user_token: &str,
+    storage: &Storage,
+) -> Result<db::User, RequestErrorResponse> {
+    match storage.find_user(user_token) {
+        Some(user) => Ok(user),
         None => Err(RequestError::not_found("User not found").into()),
     }
 }
+
+/// Replace a registered user, failing with 404 if the user is not registered
+fn update_user(user: &db::User, storage: &Storage) -> Result<(), RequestErrorResponse> {
+    match storage.update_user(user) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(RequestError::not_found("User not found").into()),
+        Err(error) => panic!("Failed updating user: {}", error),
+    }
+}
+
+#[test]
+fn test_weight_routes() {
+    use memory::{save_test_samples, MemoryStorage};
+    use rocket::http::ContentType;
+    use rocket::local::Client;
+    use server;
+
+    let storage = MemoryStorage::new();
+    let ids: Vec<String> = save_test_samples(&storage, "task", &["a", "b"])
+        .into_iter()
+        .map(|id| id.to_hex())
+        .collect();
+
+    let task = db::Task {
+        name: "task".to_string(),
+        manifest: manifest::Manifest::default(),
+    };
+    storage.save_task(&task).unwrap();
+
+    let client = Client::new(server::build(Config::default(), Box::new(storage))).unwrap();
+
+    let mut response = client
+        .post("/user")
+        .header(ContentType::JSON)
+        .body(
+            r#"{"age": 30, "gender": "other", "education": "master", "occupation": "teaching",
+                "from": null, "source": "url", "task": "task", "pre_questionnaire": null,
+                "browser": null}"#,
+        )
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    let token = body.split('"').nth(3).unwrap().to_string();
+
+    let weight = |weight: f32| {
+        format!(
+            r#"{{"token": "{}", "fullscreen": false, "video_size": 720, "metric": "pleasing",
+                "a": "{}", "b": "{}", "weight": {}}}"#,
+            token, ids[0], ids[1], weight
+        )
+    };
+
+    let post_weight = |body: String| {
+        client
+            .post("/weight")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .status()
+    };
+
+    assert_eq!(post_weight(weight(2.0)), Status::BadRequest);
+    assert_eq!(post_weight(weight(9.0)), Status::Ok);
+    assert_eq!(post_weight(weight(9.0)), Status::BadRequest);
+
+    // The pair has not been presented again with the sides swapped
+    let repeat = format!(
+        r#"{{"token": "{}", "fullscreen": false, "video_size": 720, "metric": "pleasing",
+            "a": "{}", "b": "{}", "weight": 9, "repeat": true}}"#,
+        token, ids[1], ids[0]
+    );
+    assert_eq!(post_weight(repeat), Status::BadRequest);
+
+    let mut response = client.get(format!("/task/{}", token)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.body_string().unwrap().contains(r#""pairs":[]"#));
+
+    let mut response = client.get("/task/task/manifest").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(!response.body_string().unwrap().contains("gold"));
+    let response = client.get("/task/unknown/manifest").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn test_admin_routes() {
+    use cfg::Admin as AdminCredentials;
+    use memory::MemoryStorage;
+    use rocket::http::Header;
+    use rocket::local::Client;
+    use server;
+
+    let config = Config {
+        admin: Some(AdminCredentials {
+            user: "user".to_string(),
+            password: "password".to_string(),
+        }),
+        ..Config::default()
+    };
+    let client = Client::new(server::build(config, Box::new(MemoryStorage::new()))).unwrap();
+    let get_triads = |authorization: Option<&'static str>| {
+        let mut request = client.get("/admin/task/task/triads/pleasing");
+        if let Some(authorization) = authorization {
+            request = request.header(Header::new("Authorization", authorization));
+        }
+        request.dispatch().status()
+    };
+
+    assert_eq!(get_triads(None), Status::Unauthorized);
+    assert_eq!(get_triads(Some("Basic dXNlcjp3cm9uZw==")), Status::Unauthorized);
+    assert_eq!(get_triads(Some("Basic dXNlcjpwYXNzd29yZA==")), Status::Ok);
+
+    let client =
+        Client::new(server::build(Config::default(), Box::new(MemoryStorage::new()))).unwrap();
+    let response = client.get("/admin/task/task/triads/pleasing").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
@@ -0,0 +1,186 @@
+/// Strongest preference that can be expressed on any scale, which is the weight that all
+/// scales are normalized to
+pub const MAX_WEIGHT: f32 = 9.0;
+
+/// Largest difference in the logarithm of a weight and the value of the scale it is snapped to
+const TOLERANCE: f64 = 1.0e-3;
+
+/// How users weight a pair, where a weight above 1 prefers the right sample and its reciprocal
+/// prefers the left sample equally much
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Scale {
+    /// Saaty's scale, with the integer weights 1 to 9 and their reciprocals
+    Saaty,
+    /// A number of points on each side of equal, evenly spread from 1 to 9, such as the
+    /// 7-point slider with 3 points on each side
+    Points { points: u32 },
+    /// A choice of either sample, without equal, where the chosen sample is strongly preferred
+    Binary,
+    /// Any weight from `1 / max` to `max`, which is rescaled to the range of the other scales
+    Continuous { max: f32 },
+}
+
+impl Default for Scale {
+    fn default() -> Scale {
+        Scale::Points { points: 3 }
+    }
+}
+
+impl Scale {
+    /// Weights that can be given, from the strongest preference for the left sample to the
+    /// strongest for the right sample, or `None` if the scale is continuous
+    pub fn weights(&self) -> Option<Vec<f32>> {
+        // Weights preferring the right sample, and whether equal can be chosen
+        let (right, equal): (Vec<f32>, bool) = match *self {
+            Scale::Saaty => ((2..10).map(|k| k as f32).collect(), true),
+            Scale::Points { points } => (
+                (1..(points + 1))
+                    .map(|k| 1.0 + (MAX_WEIGHT - 1.0) * k as f32 / points as f32)
+                    .collect(),
+                true,
+            ),
+            Scale::Binary => (vec![MAX_WEIGHT], false),
+            Scale::Continuous { .. } => return None,
+        };
+
+        let mut weights: Vec<f32> = right.iter().rev().map(|&weight| 1.0 / weight).collect();
+        if equal {
+            weights.push(1.0);
+        }
+        weights.extend(right);
+        Some(weights)
+    }
+
+    /// Check that a weight is on the scale, and convert it to the range from `1 / MAX_WEIGHT`
+    /// to `MAX_WEIGHT`. Weights of a discrete scale are snapped to the nearest value, to
+    /// remove rounding errors of the client.
+    pub fn normalize(&self, weight: f32) -> Result<f32, String> {
+        if !weight.is_finite() || weight <= 0.0 {
+            return Err(format!("Weight {} is not a positive number", weight));
+        }
+        let log_weight = f64::from(weight).ln();
+
+        match *self {
+            Scale::Continuous { max } => {
+                let log_max = f64::from(max).ln();
+                if log_weight.abs() > log_max + TOLERANCE {
+                    return Err(format!("Weight {} is outside of 1/{} to {}", weight, max, max));
+                }
+
+                let log_max_weight = f64::from(MAX_WEIGHT).ln();
+                let log_normalized = log_weight / log_max * log_max_weight;
+                let log_normalized =
+                    f64::min(f64::max(log_normalized, -log_max_weight), log_max_weight);
+                Ok(log_normalized.exp() as f32)
+            }
+            _ => {
+                let nearest = self.weights()
+                    .unwrap()
+                    .into_iter()
+                    .map(|value| (value, (f64::from(value).ln() - log_weight).abs()))
+                    .fold(None, |best: Option<(f32, f64)>, candidate| match best {
+                        Some(best) if best.1 <= candidate.1 => Some(best),
+                        _ => Some(candidate),
+                    })
+                    .unwrap();
+
+                if nearest.1 > TOLERANCE {
+                    Err(format!("Weight {} is not on the scale", weight))
+                } else {
+                    Ok(nearest.0)
+                }
+            }
+        }
+    }
+
+    /// Map a weight in the range from `1 / MAX_WEIGHT` to `MAX_WEIGHT` onto the scale, by the
+    /// ratio of its logarithm to that of `MAX_WEIGHT`. A discrete scale gives its value with
+    /// the nearest ratio, and fails if two values are equally near.
+    pub fn map_normalized(&self, weight: f32) -> Result<f32, String> {
+        if !weight.is_finite() || weight <= 0.0 {
+            return Err(format!("Weight {} is not a positive number", weight));
+        }
+        let log_max_weight = f64::from(MAX_WEIGHT).ln();
+        let ratio = f64::min(
+            f64::max(f64::from(weight).ln() / log_max_weight, -1.0),
+            1.0,
+        );
+
+        let weights = match self.weights() {
+            Some(weights) => weights,
+            None => return Ok((ratio * log_max_weight).exp() as f32),
+        };
+        let mut distances: Vec<(f32, f64)> = weights
+            .into_iter()
+            .map(|value| {
+                let distance = (f64::from(value).ln() / log_max_weight - ratio).abs();
+                (value, distance)
+            })
+            .collect();
+        distances.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        if distances.len() > 1 && distances[1].1 - distances[0].1 < TOLERANCE {
+            Err(format!("Weight {} is as near to two values of the scale", weight))
+        } else {
+            Ok(distances[0].0)
+        }
+    }
+
+    /// Problems with the settings of the scale
+    pub fn validate(&self) -> Option<String> {
+        match *self {
+            Scale::Points { points } if points == 0 => {
+                Some("scale.points must be positive".to_string())
+            }
+            Scale::Continuous { max } if !(max > 1.0 && max.is_finite()) => {
+                Some("scale.max must be a number above 1".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_weights() {
+    assert_eq!(Scale::Binary.weights(), Some(vec![1.0 / 9.0, 9.0]));
+    assert_eq!(Scale::Saaty.weights().unwrap().len(), 17);
+    assert_eq!(
+        Scale::Points { points: 2 }.weights(),
+        Some(vec![1.0 / 9.0, 1.0 / 5.0, 1.0, 5.0, 9.0])
+    );
+    assert_eq!(Scale::Continuous { max: 9.0 }.weights(), None);
+}
+
+#[test]
+fn test_normalize() {
+    let points = Scale::default();
+    assert_eq!(points.normalize(1.0), Ok(1.0));
+    assert_eq!(points.normalize(1.0 / (1.0 + 8.0 / 3.0 * 2.0)), Ok(1.0 / (1.0 + 16.0 / 3.0)));
+    assert!(points.normalize(2.0).is_err());
+    assert!(points.normalize(0.0).is_err());
+    assert!(points.normalize(-1.0).is_err());
+    assert!(points.normalize(::std::f32::NAN).is_err());
+
+    assert_eq!(Scale::Saaty.normalize(7.0001), Ok(7.0));
+    assert!(Scale::Binary.normalize(1.0).is_err());
+
+    let continuous = Scale::Continuous { max: 3.0 };
+    assert!((continuous.normalize(3.0).unwrap() - 9.0).abs() < 1.0e-4);
+    assert!((continuous.normalize(1.0 / 3.0).unwrap() - 1.0 / 9.0).abs() < 1.0e-4);
+    assert!(continuous.normalize(4.0).is_err());
+}
+
+#[test]
+fn test_map_normalized() {
+    let legacy = 1.0 + 8.0 / 3.0;
+    assert_eq!(Scale::Saaty.map_normalized(legacy), Ok(4.0));
+    assert_eq!(Scale::Saaty.map_normalized(1.0 / legacy), Ok(1.0 / 4.0));
+    assert_eq!(Scale::Binary.map_normalized(legacy), Ok(9.0));
+    assert!(Scale::Binary.map_normalized(1.0).is_err());
+    assert_eq!(Scale::default().map_normalized(legacy), Ok(legacy));
+
+    let continuous = Scale::Continuous { max: 3.0 };
+    assert!((continuous.map_normalized(legacy).unwrap() - legacy).abs() < 1.0e-4);
+    assert!((continuous.map_normalized(20.0).unwrap() - 9.0).abs() < 1.0e-4);
+}
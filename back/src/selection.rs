@@ -0,0 +1,257 @@
+use rand::Rng;
+use std::collections::HashMap;
+
+use choice_model::{self, Comparison, Model};
+use manifest::StoppingRule;
+
+/// Choose the next pair of samples for a user to compare in an adaptive task, or `None` when
+/// the stopping rule is met or every pair has been compared.
+///
+/// Until the comparisons connect all samples, the pairs form a chain through the samples in
+/// order. After that, a Bradley-Terry model is fitted to the comparisons, and the uncompared
+/// pair with the most uncertain outcome, weighted by the uncertainty of the scores of its
+/// samples, is chosen.
+pub fn next_pair(
+    comparisons: &[Comparison],
+    num: usize,
+    stopping: &StoppingRule,
+) -> Option<(usize, usize)> {
+    let made = comparisons.len() as u32;
+    if let Some(max_comparisons) = stopping.max_comparisons {
+        if made >= max_comparisons {
+            return None;
+        }
+    }
+
+    let mut compared = vec![vec![false; num]; num];
+    for comparison in comparisons {
+        compared[comparison.a][comparison.b] = true;
+        compared[comparison.b][comparison.a] = true;
+    }
+
+    let candidates: Vec<(usize, usize)> = (0..num)
+        .flat_map(|a| ((a + 1)..num).map(move |b| (a, b)))
+        .filter(|&(a, b)| !compared[a][b])
+        .collect();
+    if candidates.is_empty() {
+        return None;
+    }
+
+    if let Some(pair) = find_chain_link(&compared, num) {
+        return Some(pair);
+    }
+
+    let fit = match choice_model::fit(Model::BradleyTerry, comparisons, num) {
+        Some(fit) => fit,
+        None => return Some(candidates[0]),
+    };
+
+    let max_standard_error = fit.standard_errors
+        .iter()
+        .fold(0.0, |max, &error| f64::max(max, error));
+    if made >= stopping.min_comparisons && max_standard_error <= stopping.max_standard_error {
+        return None;
+    }
+
+    let information = |&(a, b): &(usize, usize)| {
+        let probability = 1.0 / (1.0 + (fit.scores[a] - fit.scores[b]).exp());
+        let variance = fit.standard_errors[a].powi(2) + fit.standard_errors[b].powi(2);
+        probability * (1.0 - probability) * variance
+    };
+
+    candidates.into_iter().fold(None, |best, pair| match best {
+        Some(best) if information(&best) >= information(&pair) => Some(best),
+        _ => Some(pair),
+    })
+}
+
+/// Assign `k` pairs of samples to a user, where `coverage` is how many other users have
+/// compared or been assigned each pair.
+///
+/// When `k` allows it, the pairs start with a spanning tree of the least covered pairs (Prim's
+/// algorithm), so that the user's comparisons connect all samples. The remaining pairs are the
+/// least covered ones. Ties are broken at random.
+pub fn assign_balanced<R: Rng>(
+    coverage: &[Vec<u32>],
+    k: usize,
+    rng: &mut R,
+) -> Vec<(usize, usize)> {
+    let num = coverage.len();
+    let k = usize::min(k, num * num.saturating_sub(1) / 2);
+
+    let mut order: Vec<usize> = (0..num).collect();
+    rng.shuffle(&mut order);
+
+    let mut assigned = Vec::with_capacity(k);
+    if num > 0 && k + 1 >= num {
+        let (mut connected, mut unconnected) = (vec![order[0]], order[1..].to_vec());
+        while !unconnected.is_empty() {
+            // Both lists are in random order, so ties go to a random pair
+            let (i, other) = unconnected
+                .iter()
+                .enumerate()
+                .flat_map(|(i, &sample)| connected.iter().map(move |&other| (i, sample, other)))
+                .min_by_key(|&(_, sample, other)| coverage[sample][other])
+                .map(|(i, _, other)| (i, other))
+                .unwrap();
+            let sample = unconnected.swap_remove(i);
+            connected.push(sample);
+            assigned.push((usize::min(sample, other), usize::max(sample, other)));
+        }
+    }
+
+    let mut remaining: Vec<(usize, usize)> = (0..num)
+        .flat_map(|a| ((a + 1)..num).map(move |b| (a, b)))
+        .filter(|pair| !assigned.contains(pair))
+        .collect();
+    rng.shuffle(&mut remaining);
+    remaining.sort_by_key(|&(a, b)| coverage[a][b]);
+
+    let missing = k - assigned.len();
+    assigned.extend(remaining.into_iter().take(missing));
+    assigned
+}
+
+/// Choose the sides of a pair so that each sample is shown on the left and right about equally
+/// often, where `imbalance` is how many more times each sample has been shown on the left than
+/// on the right. Returns the left and right sample, and updates the imbalance.
+pub fn orient_counterbalanced<R: Rng>(
+    imbalance: &mut HashMap<String, i32>,
+    a: String,
+    b: String,
+    rng: &mut R,
+) -> (String, String) {
+    let imbalance_a = imbalance.get(&a).cloned().unwrap_or(0);
+    let imbalance_b = imbalance.get(&b).cloned().unwrap_or(0);
+
+    let cost_a_left = (imbalance_a + 1).abs() + (imbalance_b - 1).abs();
+    let cost_b_left = (imbalance_a - 1).abs() + (imbalance_b + 1).abs();
+    let a_left = if cost_a_left == cost_b_left {
+        rng.gen()
+    } else {
+        cost_a_left < cost_b_left
+    };
+
+    let (left, right) = if a_left { (a, b) } else { (b, a) };
+    *imbalance.entry(left.clone()).or_insert(0) += 1;
+    *imbalance.entry(right.clone()).or_insert(0) -= 1;
+    (left, right)
+}
+
+/// Find the first sample that is not connected to the samples before it, and pair it with the
+/// sample right before it
+fn find_chain_link(compared: &[Vec<bool>], num: usize) -> Option<(usize, usize)> {
+    if num == 0 {
+        return None;
+    }
+
+    let mut connected = vec![false; num];
+    connected[0] = true;
+    let mut stack = vec![0];
+    while let Some(sample) = stack.pop() {
+        for other in 0..num {
+            if compared[sample][other] && !connected[other] {
+                connected[other] = true;
+                stack.push(other);
+            }
+        }
+    }
+
+    (1..num)
+        .find(|&sample| !connected[sample])
+        .map(|sample| (sample - 1, sample))
+}
+
+#[test]
+fn test_next_pair() {
+    fn comparison(a: usize, b: usize, preference: f64) -> Comparison {
+        Comparison {
+            a: a,
+            b: b,
+            preference: preference,
+        }
+    }
+    let stopping = StoppingRule {
+        max_standard_error: 0.0,
+        min_comparisons: 0,
+        max_comparisons: None,
+    };
+
+    // Start by chaining the samples together
+    assert_eq!(next_pair(&[], 4, &stopping), Some((0, 1)));
+    assert_eq!(
+        next_pair(&[comparison(0, 1, 0.9)], 4, &stopping),
+        Some((1, 2))
+    );
+
+    // Then choose among the uncompared pairs
+    let chain = [
+        comparison(0, 1, 0.9),
+        comparison(1, 2, 0.9),
+        comparison(2, 3, 0.9),
+    ];
+    let pair = next_pair(&chain, 4, &stopping).unwrap();
+    assert!(pair != (0, 1) && pair != (1, 2) && pair != (2, 3));
+
+    let limited = StoppingRule {
+        max_comparisons: Some(3),
+        ..stopping
+    };
+    assert_eq!(next_pair(&chain, 4, &limited), None);
+
+    let lenient = StoppingRule {
+        max_standard_error: 100.0,
+        ..stopping
+    };
+    assert_eq!(next_pair(&chain, 4, &lenient), None);
+}
+
+#[test]
+fn test_assign_balanced() {
+    use rand::{SeedableRng, StdRng};
+
+    let mut rng: StdRng = SeedableRng::from_seed(&[1usize][..]);
+
+    // A spanning tree connects all samples
+    let coverage = vec![vec![0; 5]; 5];
+    let assigned = assign_balanced(&coverage, 4, &mut rng);
+    assert_eq!(assigned.len(), 4);
+    let mut compared = vec![vec![false; 5]; 5];
+    for &(a, b) in &assigned {
+        compared[a][b] = true;
+        compared[b][a] = true;
+    }
+    assert_eq!(find_chain_link(&compared, 5), None);
+
+    // Well covered pairs are avoided, also in the spanning tree
+    let mut coverage = vec![vec![1; 3]; 3];
+    coverage[0][2] = 0;
+    coverage[2][0] = 0;
+    assert_eq!(assign_balanced(&coverage, 1, &mut rng), vec![(0, 2)]);
+    assert!(assign_balanced(&coverage, 2, &mut rng).contains(&(0, 2)));
+    assert_eq!(assign_balanced(&coverage, 10, &mut rng).len(), 3);
+}
+
+#[test]
+fn test_orient_counterbalanced() {
+    use rand::{SeedableRng, StdRng};
+
+    let mut rng: StdRng = SeedableRng::from_seed(&[1usize][..]);
+    let mut imbalance = HashMap::new();
+    imbalance.insert("x".to_string(), 2);
+
+    let (left, right) =
+        orient_counterbalanced(&mut imbalance, "x".to_string(), "y".to_string(), &mut rng);
+    assert_eq!((left.as_str(), right.as_str()), ("y", "x"));
+    assert_eq!(imbalance["x"], 1);
+    assert_eq!(imbalance["y"], 1);
+
+    // Every sample ends up balanced when each pair is shown twice
+    let mut imbalance = HashMap::new();
+    for _ in 0..2 {
+        for &(a, b) in &[("a", "b"), ("b", "c"), ("c", "a")] {
+            orient_counterbalanced(&mut imbalance, a.to_string(), b.to_string(), &mut rng);
+        }
+    }
+    assert!(imbalance.values().all(|&count| count == 0));
+}
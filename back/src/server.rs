@@ -1,50 +1,69 @@
-use bson::to_bson;
-use mongodb::{self, ThreadedClient};
-use mongodb::db::ThreadedDatabase;
-use mongodb::coll::Collection;
-use mongodb::coll::options::ReplaceOptions;
-use rocket;
+use rocket::{self, Rocket};
 use rocket::http::Method;
 use rocket_cors::{AllowedHeaders, AllowedOrigins, Cors};
 use serde_yaml;
 use std::{fs, io};
 use std::fs::File;
 use std::path::Path;
+use std::process;
 
 use cfg::Config;
 use db;
+use manifest::Manifest;
 use model::Sample;
 use routes::routes;
+use storage::{self, Storage};
 
-const VIDEOS_PATH: &str = "./task";
+pub fn run(config: Config) {
+    if let Err(error) = config.validate() {
+        println!("Invalid config: {}", error);
+        process::exit(1);
+    }
 
-pub fn run() {
-    let config = Config::from_env();
-    let db_client = db::connect(&config.db);
+    let storage = storage::open(&config.db);
 
-    db::init(&db_client).expect("Failed initializing DB");
+    if let Err(error) = storage.init() {
+        println!("Failed to initialize DB: {}", error);
+        process::exit(1);
+    }
     println!("Initialized DB");
 
-    scan_tasks(&db_client).expect("Failed scanning for videos");
+    scan_tasks(Path::new(&config.task_dir), &*storage).expect("Failed scanning for videos");
 
-    let mut ignition = rocket::ignite()
-        .manage(config)
-        .manage(db_client)
-        .mount("/", routes());
+    if config.admin.is_none() {
+        println!("Warning: No admin credentials are configured, so the admin routes are closed");
+    }
 
     let rocket_env = rocket::config::Environment::active()
         .expect("Something is wrong with the Rocket environment");
 
-    if rocket_env.is_dev() {
-        let allowed_origins = AllowedOrigins::all();
+    let allowed_origins = if !config.cors_origins.is_empty() {
+        let origins: Vec<&str> = config
+            .cors_origins
+            .iter()
+            .map(|origin| origin.as_str())
+            .collect();
+        let (allowed_origins, failed_origins) = AllowedOrigins::some(&origins);
+        if !failed_origins.is_empty() {
+            panic!("Invalid CORS origins: {:?}", failed_origins);
+        }
+        Some(allowed_origins)
+    } else if rocket_env.is_dev() {
+        Some(AllowedOrigins::all())
+    } else {
+        None
+    };
+
+    let mut ignition = build(config, storage);
 
+    if let Some(allowed_origins) = allowed_origins {
         let options = Cors {
             allowed_origins: allowed_origins,
             allowed_methods: vec![Method::Get, Method::Post, Method::Put]
                 .into_iter()
                 .map(From::from)
                 .collect(),
-            allowed_headers: AllowedHeaders::some(&["Accept", "Content-Type"]),
+            allowed_headers: AllowedHeaders::some(&["Accept", "Authorization", "Content-Type"]),
             ..Cors::default()
         };
 
@@ -54,7 +73,23 @@ pub fn run() {
     ignition.launch();
 }
 
-fn scan_videos(dir_path: &Path, collection: &Collection) -> Result<(), io::Error> {
+/// Rocket instance serving the routes from the storage
+pub fn build(config: Config, storage: Box<Storage>) -> Rocket {
+    rocket::ignite()
+        .manage(config)
+        .manage(storage)
+        .mount("/", routes())
+}
+
+fn to_io_error(error: storage::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error)
+}
+
+fn scan_videos(
+    dir_path: &Path,
+    manifest: &Manifest,
+    storage: &Storage,
+) -> Result<(), io::Error> {
     #[derive(Deserialize)]
     struct SampleData {
         fitness: f32,
@@ -88,7 +123,24 @@ fn scan_videos(dir_path: &Path, collection: &Collection) -> Result<(), io::Error
     names.sort();
     names.dedup();
 
+    for gold_pair in &manifest.gold.pairs {
+        for name in &[&gold_pair.better, &gold_pair.worse] {
+            if !names.contains(*name) {
+                println!(
+                    "Warning: Gold pair of task '{}' refers to unknown sample '{}'",
+                    task, name
+                );
+            }
+        }
+    }
+
     for name in names {
+        let gold = manifest
+            .gold
+            .pairs
+            .iter()
+            .any(|gold_pair| gold_pair.worse == name);
+
         let data_path = dir_path.join(name.clone() + ".data.yml");
         let data_file = File::open(&data_path);
 
@@ -102,6 +154,7 @@ fn scan_videos(dir_path: &Path, collection: &Collection) -> Result<(), io::Error
                     task: task.to_string(),
                     name: name,
                     fitness: data.fitness,
+                    gold: gold,
                 }
             }
             Err(_) => {
@@ -113,32 +166,12 @@ fn scan_videos(dir_path: &Path, collection: &Collection) -> Result<(), io::Error
                     task: task.to_string(),
                     name: name,
                     fitness: 0.0,
+                    gold: gold,
                 }
             }
         };
 
-        let sample_bson = to_bson(&sample).unwrap();
-        let sample_doc = sample_bson.as_document().unwrap();
-
-        let insertion_res = collection.replace_one(
-            doc! {
-                "task": &sample.task,
-                "name": &sample.name,
-            },
-            sample_doc.clone(),
-            Some(ReplaceOptions {
-                upsert: Some(true),
-                ..Default::default()
-            }),
-        );
-
-        if let Err(error) = insertion_res {
-            if let mongodb::Error::IoError(error) = error {
-                return Err(error);
-            }
-
-            return Err(io::Error::new(io::ErrorKind::Other, error));
-        }
+        storage.save_sample(&sample).map_err(to_io_error)?;
 
         println!("Registered video for task '{}': '{}'", task, sample.name);
     }
@@ -146,15 +179,27 @@ fn scan_videos(dir_path: &Path, collection: &Collection) -> Result<(), io::Error
     Ok(())
 }
 
-fn scan_tasks(db_client: &mongodb::Client) -> Result<(), io::Error> {
-    let collection = db_client.db(db::NAME).collection(db::COLLECTION_SAMPLE);
+fn scan_manifest(dir_path: &Path, storage: &Storage) -> Result<Manifest, io::Error> {
+    let task = db::Task {
+        name: dir_path.file_name().unwrap().to_str().unwrap().to_string(),
+        manifest: Manifest::read(dir_path)?,
+    };
+
+    storage.save_task(&task).map_err(to_io_error)?;
+
+    println!("Registered task '{}': {:?}", task.name, task.manifest.design);
+
+    Ok(task.manifest)
+}
 
-    for entry in fs::read_dir(VIDEOS_PATH)? {
+fn scan_tasks(task_dir: &Path, storage: &Storage) -> Result<(), io::Error> {
+    for entry in fs::read_dir(task_dir)? {
         let entry = entry?;
         let path = entry.path();
 
         if path.is_dir() {
-            scan_videos(&path, &collection)?;
+            let manifest = scan_manifest(&path, storage)?;
+            scan_videos(&path, &manifest, storage)?;
         }
     }
 
@@ -0,0 +1,405 @@
+use bson::oid::ObjectId;
+use rusqlite::{self, Connection};
+use rusqlite::ffi::ErrorCode;
+use rusqlite::types::ToSql;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use db::{Presentation, Task, User, Weighting};
+use model::Sample;
+use storage::{Error, Storage, WeightFilter};
+
+/// Tables with the same unique indexes as the collections in MongoDB. Records are stored as
+/// JSON, next to the columns they are looked up by. The side imbalance is counted from the
+/// presentations recorded before it was kept.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS samples (
+        id TEXT PRIMARY KEY,
+        task TEXT NOT NULL,
+        name TEXT NOT NULL,
+        gold INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        UNIQUE (task, name)
+    );
+
+    CREATE TABLE IF NOT EXISTS tasks (
+        name TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS users (
+        token TEXT NOT NULL UNIQUE,
+        public TEXT NOT NULL UNIQUE,
+        task TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS users_task ON users (task);
+
+    CREATE TABLE IF NOT EXISTS weights (
+        token TEXT NOT NULL,
+        metric TEXT NOT NULL,
+        a TEXT NOT NULL,
+        b TEXT NOT NULL,
+        repeat INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        UNIQUE (token, metric, a, b, repeat)
+    );
+    CREATE INDEX IF NOT EXISTS weights_metric_a_b ON weights (metric, a, b);
+    CREATE INDEX IF NOT EXISTS weights_a_b ON weights (a, b);
+
+    CREATE TABLE IF NOT EXISTS presentations (
+        token TEXT NOT NULL,
+        task TEXT NOT NULL,
+        a TEXT NOT NULL,
+        b TEXT NOT NULL,
+        data TEXT NOT NULL,
+        UNIQUE (token, a, b)
+    );
+    CREATE INDEX IF NOT EXISTS presentations_task ON presentations (task);
+
+    CREATE TABLE IF NOT EXISTS side_imbalance (
+        task TEXT NOT NULL,
+        sample TEXT NOT NULL,
+        imbalance INTEGER NOT NULL,
+        PRIMARY KEY (task, sample)
+    );
+    INSERT INTO side_imbalance (task, sample, imbalance)
+        SELECT task, sample, SUM(side) FROM (
+            SELECT task, a AS sample, 1 AS side FROM presentations
+            UNION ALL
+            SELECT task, b AS sample, -1 AS side FROM presentations
+        )
+        WHERE NOT EXISTS (SELECT 1 FROM side_imbalance)
+        GROUP BY task, sample;
+";
+
+/// Storage in an SQLite file, for studies run on a single machine without a database server
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Open the file at the path, creating it if it does not exist, or a database that is lost
+    /// when it is dropped if the path is `:memory:`
+    pub fn open(path: &str) -> Result<SqliteStorage, Error> {
+        let connection = Connection::open(path).map_err(from_sqlite_error)?;
+        println!("Opened SQLite DB at {}", path);
+
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<Connection> {
+        self.connection.lock().expect("Storage lock poisoned")
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn init(&self) -> Result<(), Error> {
+        self.lock()
+            .execute_batch(SCHEMA)
+            .map_err(from_sqlite_error)
+    }
+
+    fn find_samples(&self, task: &str, include_gold: bool) -> Vec<(ObjectId, Sample)> {
+        let connection = self.lock();
+        let mut statement = connection
+            .prepare(
+                "SELECT id, data FROM samples WHERE task = ?1 AND (?2 OR NOT gold) \
+                 ORDER BY rowid",
+            )
+            .expect("Failed preparing query");
+        let rows = statement
+            .query_map(&[&task, &include_gold], |row| -> (String, String) {
+                (row.get(0), row.get(1))
+            })
+            .expect("Failed retrieving samples");
+
+        let mut samples = Vec::new();
+        for row in rows {
+            let (id, data) = row.expect("Failed retrieving sample");
+            let id = ObjectId::with_string(&id).expect("Failed deserializing sample");
+            samples.push((id, from_json(&data)));
+        }
+        samples
+    }
+
+    fn find_sample(&self, id: &ObjectId) -> Option<Sample> {
+        query(
+            &self.lock(),
+            "SELECT data FROM samples WHERE id = ?1",
+            &[&id.to_hex()],
+        ).pop()
+    }
+
+    fn find_task_names(&self) -> Vec<String> {
+        let connection = self.lock();
+        let mut statement = connection
+            .prepare("SELECT task FROM samples GROUP BY task ORDER BY MIN(rowid)")
+            .expect("Failed preparing query");
+        let rows = statement
+            .query_map(&[], |row| -> String { row.get(0) })
+            .expect("Failed retrieving samples");
+
+        let mut names = Vec::new();
+        for row in rows {
+            names.push(row.expect("Failed retrieving sample"));
+        }
+        names
+    }
+
+    fn save_sample(&self, sample: &Sample) -> Result<(), Error> {
+        let connection = self.lock();
+        let data = to_json(sample);
+
+        // Updating keeps the ID of a sample that has been registered before
+        let updated = connection
+            .execute(
+                "UPDATE samples SET gold = ?3, data = ?4 WHERE task = ?1 AND name = ?2",
+                &[&sample.task, &sample.name, &sample.gold, &data],
+            )
+            .map_err(from_sqlite_error)?;
+        if updated > 0 {
+            return Ok(());
+        }
+
+        let id = ObjectId::new().map_err(|err| Error::Backend(err.to_string()))?;
+        connection
+            .execute(
+                "INSERT INTO samples (id, task, name, gold, data) VALUES (?1, ?2, ?3, ?4, ?5)",
+                &[&id.to_hex(), &sample.task, &sample.name, &sample.gold, &data],
+            )
+            .map(|_| ())
+            .map_err(from_sqlite_error)
+    }
+
+    fn find_task(&self, name: &str) -> Option<Task> {
+        query(&self.lock(), "SELECT data FROM tasks WHERE name = ?1", &[&name]).pop()
+    }
+
+    fn save_task(&self, task: &Task) -> Result<(), Error> {
+        self.lock()
+            .execute(
+                "INSERT OR REPLACE INTO tasks (name, data) VALUES (?1, ?2)",
+                &[&task.name, &to_json(task)],
+            )
+            .map(|_| ())
+            .map_err(from_sqlite_error)
+    }
+
+    fn find_user(&self, token: &str) -> Option<User> {
+        query(&self.lock(), "SELECT data FROM users WHERE token = ?1", &[&token]).pop()
+    }
+
+    fn find_users(&self, task: &str) -> Vec<User> {
+        query(
+            &self.lock(),
+            "SELECT data FROM users WHERE task = ?1 ORDER BY rowid",
+            &[&task],
+        )
+    }
+
+    fn insert_user(&self, user: &User) -> Result<(), Error> {
+        self.lock()
+            .execute(
+                "INSERT INTO users (token, public, task, data) VALUES (?1, ?2, ?3, ?4)",
+                &[&user.token, &user.public, &user.task, &to_json(user)],
+            )
+            .map(|_| ())
+            .map_err(from_sqlite_error)
+    }
+
+    fn update_user(&self, user: &User) -> Result<bool, Error> {
+        self.lock()
+            .execute(
+                "UPDATE users SET public = ?2, task = ?3, data = ?4 WHERE token = ?1",
+                &[&user.token, &user.public, &user.task, &to_json(user)],
+            )
+            .map(|updated| updated > 0)
+            .map_err(from_sqlite_error)
+    }
+
+    fn find_weightings(&self, filter: &WeightFilter) -> Vec<Weighting> {
+        // Conditions on lists of values are left to the filter, to stay below the limit on
+        // the number of parameters of a query
+        let metric = filter.metric.as_ref().map(|metric| metric.as_str().to_string());
+        let pair = filter
+            .pair
+            .as_ref()
+            .map(|&(ref a, ref b)| (a.to_hex(), b.to_hex()));
+
+        let mut sql = "SELECT data FROM weights WHERE 1".to_string();
+        let mut params: Vec<&ToSql> = Vec::new();
+        if let Some(ref token) = filter.token {
+            sql.push_str(" AND token = ?");
+            params.push(token);
+        }
+        if let Some(ref metric) = metric {
+            sql.push_str(" AND metric = ?");
+            params.push(metric);
+        }
+        if let Some((ref a, ref b)) = pair {
+            sql.push_str(" AND a = ? AND b = ?");
+            params.push(a);
+            params.push(b);
+        }
+        if let Some(ref repeat) = filter.repeat {
+            sql.push_str(" AND repeat = ?");
+            params.push(repeat);
+        }
+        sql.push_str(" ORDER BY rowid");
+
+        query(&self.lock(), &sql, &params)
+            .into_iter()
+            .filter(|weighting| filter.matches(weighting))
+            .collect()
+    }
+
+    fn insert_weighting(&self, weighting: &Weighting) -> Result<(), Error> {
+        self.lock()
+            .execute(
+                "INSERT INTO weights (token, metric, a, b, repeat, data) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                &[
+                    &weighting.token,
+                    &weighting.metric.as_str().to_string(),
+                    &weighting.a.to_hex(),
+                    &weighting.b.to_hex(),
+                    &weighting.repeat,
+                    &to_json(weighting),
+                ],
+            )
+            .map(|_| ())
+            .map_err(from_sqlite_error)
+    }
+
+    fn update_weighting(&self, weighting: &Weighting) -> Result<bool, Error> {
+        self.lock()
+            .execute(
+                "UPDATE weights SET data = ?6 \
+                 WHERE token = ?1 AND metric = ?2 AND a = ?3 AND b = ?4 AND repeat = ?5",
+                &[
+                    &weighting.token,
+                    &weighting.metric.as_str().to_string(),
+                    &weighting.a.to_hex(),
+                    &weighting.b.to_hex(),
+                    &weighting.repeat,
+                    &to_json(weighting),
+                ],
+            )
+            .map(|updated| updated > 0)
+            .map_err(from_sqlite_error)
+    }
+
+    fn find_user_presentations(&self, token: &str) -> Vec<Presentation> {
+        query(
+            &self.lock(),
+            "SELECT data FROM presentations WHERE token = ?1 ORDER BY rowid",
+            &[&token],
+        )
+    }
+
+    fn find_side_imbalance(&self, task: &str) -> HashMap<String, i32> {
+        let connection = self.lock();
+        let mut statement = connection
+            .prepare("SELECT sample, imbalance FROM side_imbalance WHERE task = ?1")
+            .expect("Failed preparing query");
+        let rows = statement
+            .query_map(&[&task], |row| -> (String, i32) { (row.get(0), row.get(1)) })
+            .expect("Failed retrieving side imbalance");
+
+        let mut imbalance = HashMap::new();
+        for row in rows {
+            let (sample, count) = row.expect("Failed retrieving side imbalance");
+            imbalance.insert(sample, count);
+        }
+        imbalance
+    }
+
+    fn record_presentation(&self, presentation: &Presentation) -> Result<(), Error> {
+        let mut connection = self.lock();
+        let transaction = connection.transaction().map_err(from_sqlite_error)?;
+        let (a, b) = (presentation.a.to_hex(), presentation.b.to_hex());
+        let inserted = transaction
+            .execute(
+                "INSERT OR IGNORE INTO presentations (token, task, a, b, data) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                &[
+                    &presentation.token,
+                    &presentation.task,
+                    &a,
+                    &b,
+                    &to_json(presentation),
+                ],
+            )
+            .map_err(from_sqlite_error)?;
+        if inserted == 0 {
+            return Ok(());
+        }
+
+        for &(sample, side) in &[(&a, 1), (&b, -1)] {
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO side_imbalance (task, sample, imbalance) \
+                     VALUES (?1, ?2, 0)",
+                    &[&presentation.task, sample],
+                )
+                .and_then(|_| {
+                    transaction.execute(
+                        "UPDATE side_imbalance SET imbalance = imbalance + ?3 \
+                         WHERE task = ?1 AND sample = ?2",
+                        &[&presentation.task, sample, &side],
+                    )
+                })
+                .map_err(from_sqlite_error)?;
+        }
+        transaction.commit().map_err(from_sqlite_error)
+    }
+}
+
+/// Records of a query that selects the `data` column
+fn query<T: DeserializeOwned>(connection: &Connection, sql: &str, params: &[&ToSql]) -> Vec<T> {
+    let mut statement = connection.prepare(sql).expect("Failed preparing query");
+    let rows = statement
+        .query_map(params, |row| -> String { row.get(0) })
+        .expect("Failed querying DB");
+
+    let mut records = Vec::new();
+    for row in rows {
+        records.push(from_json(&row.expect("Failed retrieving record")));
+    }
+    records
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("Failed serializing record")
+}
+
+fn from_json<T: DeserializeOwned>(data: &str) -> T {
+    serde_json::from_str(data).expect("Failed deserializing record")
+}
+
+fn from_sqlite_error(error: rusqlite::Error) -> Error {
+    match error {
+        rusqlite::Error::SqliteFailure(ref failure, _)
+            if failure.code == ErrorCode::ConstraintViolation =>
+        {
+            Error::Duplicate
+        }
+        error => Error::Backend(error.to_string()),
+    }
+}
+
+#[test]
+fn test_sqlite_storage() {
+    use storage::check_storage;
+
+    let storage = SqliteStorage::open(":memory:").unwrap();
+    storage.init().unwrap();
+    storage.init().unwrap();
+    check_storage(&storage);
+}
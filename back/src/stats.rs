@@ -1,20 +1,308 @@
-use bson::{from_bson, Bson};
 use bson::oid::ObjectId;
-use mongodb::{self, ThreadedClient};
-use mongodb::db::{Database, ThreadedDatabase};
 use na::{DMatrix, DVector};
+use rand::{Rng, SeedableRng, StdRng};
 use serde_enum;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::error;
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
-use db::{self, Weighting};
-use cfg;
+use agreement::{self, Concordance, PairVotes};
+use bootstrap::{self, Resample};
+use choice_model::{self, Comparison, Model};
+use correlation::{self, Correlation};
+use db::Weighting;
+use distribution;
+use manifest::StoppingRule;
 use model::Metric;
+use selection;
+use storage::{Storage, WeightFilter};
+use scale::MAX_WEIGHT;
+
+/// Method used to derive priorities from a pairwise comparison matrix.
+///
+/// When comparisons are missing, `Eigenvector` uses Harker's method, while `Mean` and
+/// `LogLeastSquares` use the logarithmic least squares method.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PriorityMethod {
+    /// Mean of the rows of the column-normalized matrix (approximation)
+    Mean,
+    /// Principal eigenvector of the matrix, found through power iteration
+    Eigenvector,
+    /// Logarithmic least squares (row geometric mean for complete matrices)
+    LogLeastSquares,
+}
+
+impl Default for PriorityMethod {
+    fn default() -> PriorityMethod {
+        PriorityMethod::Mean
+    }
+}
+
+/// How the judgments of several users are combined into a group ranking
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    /// Aggregation of individual judgments: Element-wise geometric mean of the users' matrices
+    Aij,
+    /// Aggregation of individual priorities: Geometric mean of the users' priorities
+    Aip,
+}
+
+impl Default for Aggregation {
+    fn default() -> Aggregation {
+        Aggregation::Aij
+    }
+}
+
+/// Restricts which users of a task are included in a group ranking
+#[derive(Default)]
+pub struct UserFilter {
+    pub source: Option<String>,
+    pub gender: Option<String>,
+    pub education: Option<String>,
+    /// Only include users that have (or have not) compared all pairs
+    pub complete: Option<bool>,
+    /// Also include users that failed the attention checks of the gold pairs
+    pub include_failed_gold: bool,
+}
+
+/// Field of a user that agreement statistics can be broken down by
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    Source,
+    Gender,
+    Education,
+}
+
+/// Maximum number of power iterations before giving up on convergence
+const EIGENVECTOR_MAX_ITERATIONS: usize = 1000;
+/// Largest change in any priority between two iterations to consider it converged
+const EIGENVECTOR_TOLERANCE: f32 = 1.0e-7;
+
+/// Saaty's random consistency index for matrices of size 1 to 15
+const RANDOM_INDEX: [f32; 15] = [
+    0.0, 0.0, 0.58, 0.90, 1.12, 1.24, 1.32, 1.41, 1.45, 1.49, 1.51, 1.48, 1.56, 1.57, 1.59
+];
 
 #[derive(Serialize, Deserialize)]
 pub struct SampleWeight {
     pub name: String,
     pub weight: f32,
+    /// Bootstrap confidence interval of the weight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<(f32, f32)>,
+    /// Bootstrap probability of the sample being at each rank, starting with the best
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank_probabilities: Option<Vec<f32>>,
+}
+
+/// Priorities of all samples together with how consistent the judgments behind them are
+#[derive(Serialize, Deserialize)]
+pub struct Ranking {
+    pub weights: Vec<SampleWeight>,
+    /// Only available when all pairs have been compared
+    pub consistency: Option<Consistency>,
+    /// Pairs of samples that have not been compared
+    pub missing: Vec<(String, String)>,
+}
+
+/// Priorities of all samples from the combined judgments of several users
+#[derive(Serialize, Deserialize)]
+pub struct GroupRanking {
+    pub weights: Vec<SampleWeight>,
+    /// Consistency of the aggregated matrix, only available for AIJ when all pairs are compared
+    pub consistency: Option<Consistency>,
+    /// Pairs of samples that no included user has compared
+    pub missing: Vec<(String, String)>,
+    /// Number of users included in the ranking
+    pub users: usize,
+}
+
+/// Consistency of a reciprocal pairwise comparison matrix
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Consistency {
+    /// Principal eigenvalue of the matrix
+    pub lambda_max: f32,
+    /// Consistency Index: (lambda_max - n) / (n - 1)
+    pub index: f32,
+    /// Consistency Ratio: CI divided by the random index for n samples
+    pub ratio: f32,
+}
+
+/// Circular triads in the strict preferences of a user
+#[derive(Serialize, Deserialize)]
+pub struct Transitivity {
+    /// Number of circular triads (A > B, B > C, C > A)
+    pub circular_triads: usize,
+    /// Kendall's coefficient of consistence, only available when all pairs have been compared
+    pub consistence: Option<f32>,
+    /// Samples of each circular triad, where each sample is preferred over the next
+    pub triads: Vec<(String, String, String)>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserTransitivity {
+    /// Public token of the user
+    pub user: String,
+    pub transitivity: Transitivity,
+}
+
+/// Whether a user, or all users of a task, favor the sample on one side of a pair
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct SideBias {
+    /// Number of comparisons, including ties
+    pub comparisons: usize,
+    /// Comparisons where the sample on the left was preferred
+    pub left: usize,
+    /// Comparisons where the sample on the right was preferred
+    pub right: usize,
+    /// Mean strength of the preference for the right sample, from -1 (always the left one as
+    /// much as possible) to 1 (always the right one as much as possible)
+    pub mean_strength: f64,
+    /// Two-sided p-value of a sign test of both sides being preferred equally often
+    pub p_value: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserSideBias {
+    /// Public token of the user
+    pub user: String,
+    pub bias: SideBias,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TaskSideBias {
+    /// Side bias over the comparisons of all users
+    pub task: SideBias,
+    pub users: Vec<UserSideBias>,
+}
+
+/// Test-retest reliability of a user, from the pairs the user has weighted twice
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Reliability {
+    /// Number of pairs that have been weighted twice
+    pub repeated: usize,
+    /// Fraction of repeated pairs where the same sample was preferred both times, or neither
+    pub agreement: Option<f64>,
+    /// Pearson correlation between the strengths of the first and second preference, only
+    /// available with at least three repeated pairs
+    pub correlation: Option<f64>,
+    /// Mean absolute difference between the strengths of the first and second preference, from
+    /// 0 to 2
+    pub mean_absolute_difference: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserReliability {
+    /// Public token of the user
+    pub user: String,
+    pub reliability: Reliability,
+}
+
+/// How a user did on the gold pairs of a task
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct GoldScore {
+    /// Number of gold pairs the user has compared
+    pub checks: usize,
+    /// Number of gold pairs where the user preferred the better sample
+    pub passed: usize,
+    pub pass_rate: Option<f64>,
+    /// Whether the pass rate is below the minimum pass rate of the task
+    pub failed: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserGoldScore {
+    /// Public token of the user
+    pub user: String,
+    pub score: GoldScore,
+}
+
+/// How much the users of a task, or of a group of them, agree with each other
+#[derive(Serialize, Deserialize)]
+pub struct Agreement {
+    /// Value of the field the users were grouped by, if any
+    pub group: Option<String>,
+    /// Number of users that have compared at least one pair
+    pub users: usize,
+    /// Number of users whose comparisons connect all samples, which are included in Kendall's W
+    pub ranked_users: usize,
+    /// Kendall's W over the rankings of the users
+    pub concordance: Option<Concordance>,
+    /// Kendall's u over the comparisons of the users
+    pub kendall_u: Option<f64>,
+}
+
+/// A ranking of the samples of a task, which can be correlated with another ranking
+#[derive(Clone, Debug, PartialEq)]
+pub enum RankingSource {
+    /// Fitness of the samples, as computed when they were generated
+    Technical,
+    /// Ranking of a single user
+    User(Metric),
+    /// Group ranking from all users of the task
+    Crowd(Metric),
+}
+
+/// Prefix of crowd rankings, such as `crowd_pleasing`
+const CROWD_PREFIX: &str = "crowd_";
+
+impl FromStr for RankingSource {
+    type Err = ();
+
+    /// Parse `technical`, a metric such as `pleasing`, or a crowd metric such as `crowd_pleasing`
+    fn from_str(s: &str) -> Result<RankingSource, ()> {
+        if s == "technical" {
+            Ok(RankingSource::Technical)
+        } else if s.starts_with(CROWD_PREFIX) {
+            Metric::new(&s[CROWD_PREFIX.len()..]).map(RankingSource::Crowd)
+        } else {
+            Metric::new(s).map(RankingSource::User)
+        }
+    }
+}
+
+impl Display for RankingSource {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            RankingSource::Technical => write!(f, "technical"),
+            RankingSource::User(ref metric) => write!(f, "{}", metric),
+            RankingSource::Crowd(ref metric) => write!(f, "{}{}", CROWD_PREFIX, metric),
+        }
+    }
+}
+
+/// Rank correlation between two rankings of the same samples
+#[derive(Serialize, Deserialize)]
+pub struct RankCorrelation {
+    pub a: String,
+    pub b: String,
+    /// Number of samples present in both rankings
+    pub samples: usize,
+    /// Undefined when all samples are tied in either ranking
+    pub kendall_tau: Option<Correlation>,
+    /// Undefined when all samples are tied in either ranking
+    pub spearman_rho: Option<Correlation>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SampleScore {
+    pub name: String,
+    pub score: f64,
+    pub standard_error: f64,
+}
+
+/// Scores of all samples from fitting a paired comparison model
+#[derive(Serialize, Deserialize)]
+pub struct ModelRanking {
+    pub model: Model,
+    pub scores: Vec<SampleScore>,
+    pub log_likelihood: f64,
+    /// Number of comparisons the model was fitted to
+    pub comparisons: usize,
 }
 
 #[derive(Debug)]
@@ -23,9 +311,42 @@ struct SampleSet {
     ids: Vec<ObjectId>,
 }
 
+/// A known value of a pairwise comparison matrix, where col < row
+#[derive(Debug, Clone, Copy)]
+struct Judgment {
+    col: usize,
+    row: usize,
+    value: f32,
+}
+
+/// Group priorities together with the properties of the judgments they came from
+struct Aggregate {
+    priorities: DVector<f32>,
+    consistency: Option<Consistency>,
+    missing: Vec<(usize, usize)>,
+    users: usize,
+}
+
+/// Pairwise comparison matrix where comparisons that have not been made are 0
+#[derive(Debug)]
+struct WeightMatrix {
+    values: DMatrix<f32>,
+    /// Column and row of each pair that has not been compared, where column < row
+    missing: Vec<(usize, usize)>,
+}
+
+impl WeightMatrix {
+    fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     MissingWeights,
+    Disconnected,
+    /// A user ranking was asked for without a user
+    MissingToken,
 }
 
 impl Display for Error {
@@ -38,6 +359,8 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::MissingWeights => "missing weights",
+            Error::Disconnected => "comparisons do not connect all samples",
+            Error::MissingToken => "a user token is needed for a user ranking",
         }
     }
 }
@@ -46,168 +369,1843 @@ pub fn calculate_sample_weights(
     task: &str,
     token: &str,
     metric: &Metric,
-    db_client: &mongodb::Client,
-) -> Result<Vec<SampleWeight>, Error> {
-    let db = db_client.db(db::NAME);
+    method: PriorityMethod,
+    bootstrap: Option<&bootstrap::Options>,
+    storage: &Storage,
+) -> Result<Ranking, Error> {
+    let sample_set = get_sample_set(task, storage);
+    let weight_matrix = make_weight_matrix(token, metric, &sample_set, storage);
 
-    let sample_set = get_sample_set(task, &db);
-    let mut weight_matrix = make_weight_matrix(token, metric, &sample_set, &db)?;
-    normalize_weight_matrix(&mut weight_matrix, sample_set.num);
-    let criteria_weights = calculate_criteria_weights(&weight_matrix, sample_set.num);
+    rank_weight_matrix(weight_matrix, &sample_set, method, bootstrap)
+}
 
-    Ok(make_sample_weights(&criteria_weights, &sample_set.ids))
+/// Weights of all users of a task, loaded with a single query so that the statistics of many
+/// users can be calculated without further round-trips to the database
+pub struct TaskWeights {
+    sample_set: SampleSet,
+    weightings: HashMap<String, Vec<Weighting>>,
 }
 
-pub fn print_stats(task: &str, token: &str, metric: &Metric, cfg: &cfg::Db) -> Result<(), Error> {
-    let db_client = db::connect(cfg);
-    let db = db_client.db(db::NAME);
+impl TaskWeights {
+    pub fn load(task: &str, metric: &Metric, storage: &Storage) -> TaskWeights {
+        let sample_set = get_sample_set(task, storage);
+        let weightings = group_by_user(load_weightings(None, metric, &sample_set, storage));
 
-    let sample_set = get_sample_set(task, &db);
-    println!("N: {}", sample_set.num);
+        TaskWeights {
+            sample_set: sample_set,
+            weightings: weightings,
+        }
+    }
+
+    pub fn calculate_sample_weights(
+        &self,
+        token: &str,
+        method: PriorityMethod,
+        bootstrap: Option<&bootstrap::Options>,
+    ) -> Result<Ranking, Error> {
+        rank_weight_matrix(self.weight_matrix(token), &self.sample_set, method, bootstrap)
+    }
+
+    pub fn calculate_sample_consistency(&self, token: &str) -> Result<Consistency, Error> {
+        complete_consistency(&self.weight_matrix(token), self.sample_set.num)
+    }
+
+    pub fn calculate_sample_transitivity(&self, token: &str) -> Transitivity {
+        make_transitivity(&self.weight_matrix(token), &self.sample_set)
+    }
 
-    let mut weight_matrix = make_weight_matrix(token, metric, &sample_set, &db)?;
-    println!("Weight matrix: {}", weight_matrix);
+    fn weight_matrix(&self, token: &str) -> WeightMatrix {
+        match self.weightings.get(token) {
+            Some(weightings) => build_weight_matrix(weightings, &self.sample_set),
+            None => build_weight_matrix(&[], &self.sample_set),
+        }
+    }
+}
+
+/// Calculate the ranking of a task from the judgments of all of its users that match `filter`
+pub fn calculate_group_weights(
+    task: &str,
+    metric: &Metric,
+    method: PriorityMethod,
+    aggregation: Aggregation,
+    filter: &UserFilter,
+    bootstrap: Option<&bootstrap::Options>,
+    storage: &Storage,
+) -> Result<GroupRanking, Error> {
+    let sample_set = get_sample_set(task, storage);
+    let num = sample_set.num;
+
+    let mut tokens = get_user_tokens(task, filter, storage);
+    if !filter.include_failed_gold {
+        let failed = find_failed_gold_users(task, metric, storage);
+        tokens.retain(|token| !failed.contains(token));
+    }
+    let user_judgments: Vec<Vec<Judgment>> = make_weight_matrices(
+        &tokens,
+        metric,
+        &sample_set,
+        storage,
+    ).into_iter()
+        .filter(|weight_matrix| match filter.complete {
+            Some(complete) => weight_matrix.is_complete() == complete,
+            None => true,
+        })
+        .map(|weight_matrix| find_judgments(&weight_matrix.values, num))
+        // Users without judgments would count towards the individual judgments and be resampled
+        .filter(|judgments| !judgments.is_empty())
+        .collect();
+
+    if user_judgments.is_empty() {
+        return Err(Error::MissingWeights);
+    }
+
+    let summary = bootstrap.map(|options| {
+        let estimates = bootstrap::run(options, |rng| {
+            let resampled: Vec<Vec<Judgment>> = match options.resample {
+                Resample::Users => bootstrap::resample(rng, user_judgments.len())
+                    .into_iter()
+                    .map(|user| user_judgments[user].clone())
+                    .collect(),
+                Resample::Comparisons => user_judgments
+                    .iter()
+                    .map(|judgments| resample_judgments(judgments, rng))
+                    .collect(),
+            };
+
+            aggregate(&resampled, num, method, aggregation)
+                .ok()
+                .map(|aggregate| aggregate.priorities.iter().cloned().collect())
+        });
+        bootstrap::summarize(&estimates, num, options.level)
+    });
+
+    let group = aggregate(&user_judgments, num, method, aggregation)?;
+    let mut weights = make_sample_weights(&group.priorities, &sample_set.ids);
+    if let Some(summary) = summary {
+        apply_bootstrap(&mut weights, &summary, &sample_set.ids);
+    }
 
-    normalize_weight_matrix(&mut weight_matrix, sample_set.num);
-    println!("Normalized weight matrix: {}", weight_matrix);
+    Ok(GroupRanking {
+        weights: weights,
+        consistency: group.consistency,
+        missing: make_missing_pairs(&group.missing, &sample_set.ids),
+        users: group.users,
+    })
+}
+
+pub fn print_group_stats(
+    task: &str,
+    metric: &Metric,
+    method: PriorityMethod,
+    aggregation: Aggregation,
+    filter: &UserFilter,
+    bootstrap: Option<&bootstrap::Options>,
+    storage: &Storage,
+) -> Result<(), Error> {
+    let ranking = calculate_group_weights(
+        task,
+        metric,
+        method,
+        aggregation,
+        filter,
+        bootstrap,
+        storage,
+    )?;
+    println!("Users: {}", ranking.users);
 
-    let criteria_weights = calculate_criteria_weights(&weight_matrix, sample_set.num);
-    println!("Criteria weights: {}", criteria_weights);
+    if let Some(consistency) = ranking.consistency {
+        println!("Consistency ratio: {}", consistency.ratio);
+    }
+
+    for (a, b) in ranking.missing {
+        println!("Missing: {} <> {}", a, b);
+    }
 
-    let sample_weights = make_sample_weights(&criteria_weights, &sample_set.ids);
-    for &SampleWeight { ref name, weight } in &sample_weights {
-        println!("{} <= {}", name, weight);
+    for sample_weight in &ranking.weights {
+        print_sample_weight(sample_weight);
     }
 
     Ok(())
 }
 
-fn get_sample_set(task: &str, db: &Database) -> SampleSet {
-    let sample_docs: Vec<_> = db.collection(db::COLLECTION_SAMPLE)
-        .find(Some(doc!{ "task": task }), None)
-        .unwrap()
-        .collect::<Result<_, _>>()
-        .unwrap();
-    let ids: Vec<ObjectId> = sample_docs
+/// Fit a paired comparison model to the weights of a single user
+pub fn fit_user_model(
+    task: &str,
+    token: &str,
+    metric: &Metric,
+    model: Model,
+    storage: &Storage,
+) -> Result<ModelRanking, Error> {
+    let sample_set = get_sample_set(task, storage);
+    let comparisons = load_comparisons(Some(token), metric, &sample_set, storage);
+
+    fit_model(model, &comparisons, &sample_set)
+}
+
+/// Fit a paired comparison model to the pooled weights of all users of a task that passed the
+/// attention checks
+pub fn fit_task_model(
+    task: &str,
+    metric: &Metric,
+    model: Model,
+    storage: &Storage,
+) -> Result<ModelRanking, Error> {
+    let sample_set = get_sample_set(task, storage);
+    let failed = find_failed_gold_users(task, metric, storage);
+    let weightings = load_weightings(None, metric, &sample_set, storage)
         .into_iter()
-        .map(|doc| {
-            doc.get_object_id("_id")
-                .expect("Failed deserializing documents")
-                .clone()
-        })
+        .filter(|weighting| !failed.contains(&weighting.token))
         .collect();
-    let num = ids.len();
+    let comparisons = make_comparisons(weightings, &sample_set);
 
-    SampleSet { num: num, ids: ids }
+    fit_model(model, &comparisons, &sample_set)
 }
 
-fn make_weight_matrix(
+/// Choose the next pair for a user of an adaptive task, or `None` when the user is finished
+pub fn select_next_pair(
+    task: &str,
     token: &str,
     metric: &Metric,
-    sample_set: &SampleSet,
-    db: &Database,
-) -> Result<DMatrix<f32>, Error> {
-    let SampleSet { num, ref ids, .. } = *sample_set;
+    stopping: &StoppingRule,
+    storage: &Storage,
+) -> Option<(String, String)> {
+    let sample_set = get_sample_set(task, storage);
+    let comparisons = load_comparisons(Some(token), metric, &sample_set, storage);
 
-    let metric_str = serde_enum::to_string(&metric).unwrap();
+    selection::next_pair(&comparisons, sample_set.num, stopping)
+        .map(|(a, b)| (sample_set.ids[a].to_hex(), sample_set.ids[b].to_hex()))
+}
 
-    let mut weight_matrix = DMatrix::<f32>::identity(num, num);
-    for col in 0..num {
-        for row in (col + 1)..num {
-            let a = &ids[col];
-            let b = &ids[row];
-
-            let doc = db.collection(db::COLLECTION_WEIGHT)
-                .find_one(
-                    Some(doc! {
-                        "token": token,
-                        "metric": &metric_str,
-                        "a": a.clone(),
-                        "b": b.clone(),
-                    }),
-                    None,
-                )
-                .expect("Failed quering DB");
-
-            if let Some(doc) = doc {
-                // Weight was found in column major order.
-                let weight: Weighting =
-                    from_bson(Bson::from(doc)).expect("Failed deserializing weight");
-                weight_matrix
-                    .columns_mut(col, 1)
-                    .rows_mut(row, 1)
-                    .fill(weight.weight);
-                weight_matrix
-                    .columns_mut(row, 1)
-                    .rows_mut(col, 1)
-                    .fill(1.0 / weight.weight);
-            } else {
-                // Alternatively weight is in row major order.
-                let doc = db.collection(db::COLLECTION_WEIGHT)
-                    .find_one(
-                        Some(doc! {
-                            "token": token,
-                            "metric": &metric_str,
-                            "a": b.clone(),
-                            "b": a.clone(),
-                        }),
-                        None,
-                    )
-                    .expect("Failed quering DB");
-
-                if let Some(doc) = doc {
-                    let weight: Weighting =
-                        from_bson(Bson::from(doc)).expect("Failed deserializing weight");
-                    weight_matrix
-                        .columns_mut(col, 1)
-                        .rows_mut(row, 1)
-                        .fill(1.0 / weight.weight);
-                    weight_matrix
-                        .columns_mut(row, 1)
-                        .rows_mut(col, 1)
-                        .fill(weight.weight);
-                } else {
-                    return Err(Error::MissingWeights);
-                }
+/// Assign pairs to a user of a balanced task, so that every pair is compared about equally often
+/// when counting the pairs other users have weighted or been assigned. Ties between pairs are
+/// broken by the seed of the user.
+pub fn assign_balanced_pairs(
+    task: &str,
+    token: &str,
+    metric: &Metric,
+    pairs_per_user: Option<u32>,
+    seed: usize,
+    storage: &Storage,
+) -> Vec<(ObjectId, ObjectId)> {
+    let sample_set = get_sample_set(task, storage);
+    let num = sample_set.num;
+    let indices = make_indices(&sample_set);
+
+    // Pairs of each other user, counting a pair once per user
+    let mut user_pairs: HashMap<String, HashSet<(usize, usize)>> = HashMap::new();
+    {
+        let mut add_pair = |user: &str, a: &ObjectId, b: &ObjectId| {
+            if user == token {
+                return;
             }
+            if let (Some(&a), Some(&b)) = (indices.get(a), indices.get(b)) {
+                user_pairs
+                    .entry(user.to_string())
+                    .or_insert_with(HashSet::new)
+                    .insert((usize::min(a, b), usize::max(a, b)));
+            }
+        };
+
+        for weighting in load_weightings(None, metric, &sample_set, storage) {
+            add_pair(&weighting.token, &weighting.a, &weighting.b);
+        }
+
+        for user in storage.find_users(task) {
+            for &(ref a, ref b) in user.assignment.iter().flat_map(|pairs| pairs.iter()) {
+                add_pair(&user.token, a, b);
+            }
+        }
+    }
+
+    let mut coverage = vec![vec![0u32; num]; num];
+    for pairs in user_pairs.values() {
+        for &(a, b) in pairs {
+            coverage[a][b] += 1;
+            coverage[b][a] += 1;
         }
     }
 
-    Ok(weight_matrix)
+    let k = match pairs_per_user {
+        Some(pairs_per_user) => pairs_per_user as usize,
+        None => num.saturating_sub(1),
+    };
+
+    let mut rng: StdRng = SeedableRng::from_seed(&[seed][..]);
+    selection::assign_balanced(&coverage, k, &mut rng)
+        .into_iter()
+        .map(|(a, b)| (sample_set.ids[a].clone(), sample_set.ids[b].clone()))
+        .collect()
 }
 
-fn normalize_weight_matrix(weight_matrix: &mut DMatrix<f32>, num: usize) {
-    // Normalize on columns
-    for col in 0..num {
-        let sum: f32 = weight_matrix.columns(col, 1).iter().sum();
-        let normalization = 1.0 / sum;
-        for weight in weight_matrix.columns_mut(col, 1).iter_mut() {
-            *weight *= normalization;
+pub fn print_model(
+    task: &str,
+    token: Option<&str>,
+    metric: &Metric,
+    model: Model,
+    storage: &Storage,
+) -> Result<(), Error> {
+    let ranking = match token {
+        Some(token) => fit_user_model(task, token, metric, model, storage)?,
+        None => fit_task_model(task, metric, model, storage)?,
+    };
+
+    println!("Model: {:?}", ranking.model);
+    println!("Comparisons: {}", ranking.comparisons);
+    println!("Log-likelihood: {}", ranking.log_likelihood);
+
+    for &SampleScore {
+        ref name,
+        score,
+        standard_error,
+    } in &ranking.scores
+    {
+        println!("{} <= {} (SE {})", name, score, standard_error);
+    }
+
+    Ok(())
+}
+
+/// Calculate Kendall's W and u among all users of a task, or among the users of each group.
+/// Users that failed the attention checks are left out.
+pub fn calculate_agreement(
+    task: &str,
+    metric: &Metric,
+    group_by: Option<GroupBy>,
+    storage: &Storage,
+) -> Vec<Agreement> {
+    let sample_set = get_sample_set(task, storage);
+    let failed = find_failed_gold_users(task, metric, storage);
+
+    let mut groups: BTreeMap<Option<String>, Vec<String>> = BTreeMap::new();
+    for user in storage.find_users(task) {
+        if failed.contains(&user.token) {
+            continue;
         }
+        let group = group_by.map(|group_by| match group_by {
+            GroupBy::Source => user.source.clone(),
+            GroupBy::Gender => serde_enum::to_string(&user.gender).unwrap(),
+            GroupBy::Education => serde_enum::to_string(&user.education).unwrap(),
+        });
+        groups
+            .entry(group)
+            .or_insert_with(Vec::new)
+            .push(user.token);
     }
+
+    groups
+        .into_iter()
+        .map(|(group, tokens)| {
+            let weight_matrices = make_weight_matrices(&tokens, metric, &sample_set, storage);
+            make_agreement(group, weight_matrices, sample_set.num)
+        })
+        .collect()
 }
 
-fn calculate_criteria_weights(weight_matrix: &DMatrix<f32>, num: usize) -> DVector<f32> {
-    // Calculate mean of each row
-    DVector::<f32>::from_iterator(
-        num,
-        (0..num).map(|row| {
-            weight_matrix.rows(row, 1).iter().sum::<f32>() / num as f32
-        }),
-    )
+pub fn print_agreement(
+    task: &str,
+    metric: &Metric,
+    group_by: Option<GroupBy>,
+    storage: &Storage,
+) {
+    for agreement in calculate_agreement(task, metric, group_by, storage) {
+        if let Some(ref group) = agreement.group {
+            println!("Group: {}", group);
+        }
+        println!("Users: {} ({} ranked)", agreement.users, agreement.ranked_users);
+
+        match agreement.concordance {
+            Some(concordance) => println!(
+                "Kendall's W: {} (chi-square = {}, df = {}, p = {})",
+                concordance.w,
+                concordance.chi_square,
+                concordance.degrees_of_freedom,
+                concordance.p_value
+            ),
+            None => println!("Kendall's W: undefined"),
+        }
+        match agreement.kendall_u {
+            Some(u) => println!("Kendall's u: {}", u),
+            None => println!("Kendall's u: undefined"),
+        }
+    }
 }
 
-fn make_sample_weights(criteria_weights: &DVector<f32>, ids: &[ObjectId]) -> Vec<SampleWeight> {
-    let mut sample_weights: Vec<_> = criteria_weights
-        .iter()
-        .enumerate()
-        .map(|(i, w)| {
-            SampleWeight {
-                name: ids[i].to_hex(),
-                weight: *w,
+/// Find the circular triads in the comparisons of each user of a task
+pub fn calculate_task_transitivity(
+    task: &str,
+    metric: &Metric,
+    storage: &Storage,
+) -> Vec<UserTransitivity> {
+    let sample_set = get_sample_set(task, storage);
+    let weightings = group_by_user(load_weightings(None, metric, &sample_set, storage));
+
+    get_public_tokens(task, storage)
+        .into_iter()
+        .map(|(token, public)| {
+            let weight_matrix = match weightings.get(&token) {
+                Some(weightings) => build_weight_matrix(weightings, &sample_set),
+                None => build_weight_matrix(&[], &sample_set),
+            };
+
+            UserTransitivity {
+                user: public,
+                transitivity: make_transitivity(&weight_matrix, &sample_set),
             }
         })
+        .collect()
+}
+
+/// Estimate whether the users of a task favor the sample shown on the left or on the right,
+/// both over all users and for each user that has compared at least one pair
+pub fn calculate_side_bias(
+    task: &str,
+    metric: &Metric,
+    storage: &Storage,
+) -> TaskSideBias {
+    let sample_set = get_sample_set(task, storage);
+    let weightings = load_weightings(None, metric, &sample_set, storage);
+    let task_bias = make_side_bias(&weightings
+        .iter()
+        .map(|weighting| weighting.weight)
+        .collect::<Vec<_>>());
+
+    let weightings = group_by_user(weightings);
+    let users = get_public_tokens(task, storage)
+        .into_iter()
+        .filter_map(|(token, public)| {
+            weightings.get(&token).map(|weightings| UserSideBias {
+                user: public,
+                bias: make_side_bias(&weightings
+                    .iter()
+                    .map(|weighting| weighting.weight)
+                    .collect::<Vec<_>>()),
+            })
+        })
         .collect();
-    sample_weights.sort_by(|a, b| b.weight.partial_cmp(&a.weight).unwrap());
-    sample_weights
+
+    TaskSideBias {
+        task: task_bias,
+        users: users,
+    }
+}
+
+pub fn print_side_bias(task: &str, metric: &Metric, storage: &Storage) {
+    fn print_bias(bias: &SideBias) {
+        println!(
+            "{} comparisons, left: {}, right: {}, ties: {}, mean strength: {:.3}, p: {:.4}",
+            bias.comparisons,
+            bias.left,
+            bias.right,
+            bias.comparisons - bias.left - bias.right,
+            bias.mean_strength,
+            bias.p_value
+        );
+    }
+
+    let side_bias = calculate_side_bias(task, metric, storage);
+
+    print!("Task: ");
+    print_bias(&side_bias.task);
+    for user in &side_bias.users {
+        print!("{}: ", user.user);
+        print_bias(&user.bias);
+    }
+}
+
+/// Test-retest reliability of each user of a task that has weighted repeated pairs
+pub fn calculate_reliability(
+    task: &str,
+    metric: &Metric,
+    storage: &Storage,
+) -> Vec<UserReliability> {
+    let sample_set = get_sample_set(task, storage);
+    let first: HashMap<(String, ObjectId, ObjectId), f32> =
+        load_weightings(None, metric, &sample_set, storage)
+            .into_iter()
+            .map(|weighting| {
+                (
+                    (weighting.token, weighting.a, weighting.b),
+                    weighting.weight,
+                )
+            })
+            .collect();
+
+    // Repeated pairs have the sides swapped, so their strength is negated
+    let mut strengths: HashMap<String, Vec<(f64, f64)>> = HashMap::new();
+    for repeat in load_repeated_weightings(metric, &sample_set, storage) {
+        let key = (repeat.token, repeat.b, repeat.a);
+        if let Some(&weight) = first.get(&key) {
+            strengths.entry(key.0).or_insert_with(Vec::new).push((
+                strength_from_weight(weight),
+                -strength_from_weight(repeat.weight),
+            ));
+        }
+    }
+
+    get_public_tokens(task, storage)
+        .into_iter()
+        .filter_map(|(token, public)| {
+            strengths.get(&token).map(|strengths| UserReliability {
+                user: public,
+                reliability: make_reliability(strengths),
+            })
+        })
+        .collect()
+}
+
+pub fn print_reliability(task: &str, metric: &Metric, storage: &Storage) {
+    fn format_value(value: Option<f64>) -> String {
+        value
+            .map(|value| format!("{:.3}", value))
+            .unwrap_or_else(|| "undefined".to_string())
+    }
+
+    for user in calculate_reliability(task, metric, storage) {
+        let reliability = &user.reliability;
+        println!(
+            "{}: {} repeated, agreement: {}, correlation: {}, mean absolute difference: {}",
+            user.user,
+            reliability.repeated,
+            format_value(reliability.agreement),
+            format_value(reliability.correlation),
+            format_value(reliability.mean_absolute_difference)
+        );
+    }
+}
+
+/// Score the gold pairs compared by each user of a task
+pub fn calculate_gold_scores(
+    task: &str,
+    metric: &Metric,
+    storage: &Storage,
+) -> Vec<UserGoldScore> {
+    let scores = score_gold(task, metric, storage);
+
+    get_public_tokens(task, storage)
+        .into_iter()
+        .filter_map(|(token, public)| {
+            scores.get(&token).map(|&score| UserGoldScore {
+                user: public,
+                score: score,
+            })
+        })
+        .collect()
+}
+
+pub fn print_gold_scores(task: &str, metric: &Metric, storage: &Storage) {
+    for user in calculate_gold_scores(task, metric, storage) {
+        println!(
+            "{}: {} of {} passed{}",
+            user.user,
+            user.score.passed,
+            user.score.checks,
+            if user.score.failed { ", failed" } else { "" }
+        );
+    }
+}
+
+/// Tokens of the users of a task that failed the attention checks of the gold pairs
+pub fn find_failed_gold_users(
+    task: &str,
+    metric: &Metric,
+    storage: &Storage,
+) -> HashSet<String> {
+    score_gold(task, metric, storage)
+        .into_iter()
+        .filter(|&(_, ref score)| score.failed)
+        .map(|(token, _)| token)
+        .collect()
+}
+
+/// Normalized fitness of each sample of the task
+pub fn calculate_technical_weights(task: &str, storage: &Storage) -> Vec<SampleWeight> {
+    let mut weights: Vec<_> = storage
+        .find_samples(task, false)
+        .into_iter()
+        .map(|(id, sample)| SampleWeight {
+            name: id.to_hex(),
+            weight: sample.fitness,
+            interval: None,
+            rank_probabilities: None,
+        })
+        .collect();
+
+    let normalizer = 1.0 / weights.iter().map(|weight| weight.weight).sum::<f32>();
+    for weight in &mut weights {
+        weight.weight *= normalizer;
+    }
+    weights.sort_by(|a, b| b.weight.partial_cmp(&a.weight).unwrap());
+
+    weights
+}
+
+/// Weights of a ranking source, where user and crowd rankings use the default priority method
+/// and aggregation. User rankings need the token of the user.
+pub fn calculate_source_weights(
+    task: &str,
+    token: Option<&str>,
+    source: &RankingSource,
+    storage: &Storage,
+) -> Result<Vec<SampleWeight>, Error> {
+    match *source {
+        RankingSource::Technical => Ok(calculate_technical_weights(task, storage)),
+        RankingSource::User(ref metric) => calculate_sample_weights(
+            task,
+            token.ok_or(Error::MissingToken)?,
+            metric,
+            PriorityMethod::default(),
+            None,
+            storage,
+        ).map(|ranking| ranking.weights),
+        RankingSource::Crowd(ref metric) => calculate_group_weights(
+            task,
+            metric,
+            PriorityMethod::default(),
+            Aggregation::default(),
+            &UserFilter::default(),
+            None,
+            storage,
+        ).map(|ranking| ranking.weights),
+    }
+}
+
+/// Calculate the rank correlation between two rankings of the samples of a task, where user
+/// rankings are those of the user with the given token
+pub fn calculate_rank_correlation(
+    task: &str,
+    token: Option<&str>,
+    a: RankingSource,
+    b: RankingSource,
+    storage: &Storage,
+) -> Result<RankCorrelation, Error> {
+    let weights_a = calculate_source_weights(task, token, &a, storage)?;
+    let weights_b = calculate_source_weights(task, token, &b, storage)?;
+
+    Ok(correlate_weights(&a, &weights_a, &b, &weights_b))
+}
+
+/// Calculate Kendall's tau and Spearman's rho between the samples present in both rankings
+pub fn correlate_weights(
+    a: &RankingSource,
+    weights_a: &[SampleWeight],
+    b: &RankingSource,
+    weights_b: &[SampleWeight],
+) -> RankCorrelation {
+    let (x, y): (Vec<f64>, Vec<f64>) = weights_a
+        .iter()
+        .filter_map(|weight_a| {
+            weights_b
+                .iter()
+                .find(|weight_b| weight_b.name == weight_a.name)
+                .map(|weight_b| (f64::from(weight_a.weight), f64::from(weight_b.weight)))
+        })
+        .unzip();
+
+    RankCorrelation {
+        a: a.to_string(),
+        b: b.to_string(),
+        samples: x.len(),
+        kendall_tau: correlation::kendall_tau(&x, &y),
+        spearman_rho: correlation::spearman_rho(&x, &y),
+    }
+}
+
+pub fn print_rank_correlation(
+    task: &str,
+    token: Option<&str>,
+    a: RankingSource,
+    b: RankingSource,
+    storage: &Storage,
+) -> Result<(), Error> {
+    let correlation = calculate_rank_correlation(task, token, a, b, storage)?;
+    println!("{} <> {}", correlation.a, correlation.b);
+    println!("Samples: {}", correlation.samples);
+    print_correlation("Kendall's tau", correlation.kendall_tau);
+    print_correlation("Spearman's rho", correlation.spearman_rho);
+
+    Ok(())
+}
+
+fn print_correlation(name: &str, correlation: Option<Correlation>) {
+    match correlation {
+        Some(correlation) => println!(
+            "{}: {} (p = {})",
+            name,
+            correlation.coefficient,
+            correlation.p_value
+        ),
+        None => println!("{}: undefined", name),
+    }
+}
+
+pub fn print_stats(
+    task: &str,
+    token: &str,
+    metric: &Metric,
+    method: PriorityMethod,
+    bootstrap: Option<&bootstrap::Options>,
+    storage: &Storage,
+) -> Result<(), Error> {
+    let sample_set = get_sample_set(task, storage);
+    println!("N: {}", sample_set.num);
+
+    let weight_matrix = make_weight_matrix(token, metric, &sample_set, storage);
+    println!("Weight matrix: {}", weight_matrix.values);
+
+    if weight_matrix.is_complete() {
+        let consistency = calculate_consistency(&weight_matrix.values, sample_set.num);
+        println!("Lambda max: {}", consistency.lambda_max);
+        println!("Consistency index: {}", consistency.index);
+        println!("Consistency ratio: {}", consistency.ratio);
+    } else {
+        println!("Missing pairs: {}", weight_matrix.missing.len());
+        for (a, b) in make_missing_pairs(&weight_matrix.missing, &sample_set.ids) {
+            println!("{} <> {}", a, b);
+        }
+        println!(
+            "Connected: {}",
+            is_connected(&weight_matrix.values, sample_set.num)
+        );
+    }
+
+    let transitivity = make_transitivity(&weight_matrix, &sample_set);
+    println!("Circular triads: {}", transitivity.circular_triads);
+    for &(ref a, ref b, ref c) in &transitivity.triads {
+        println!("{} > {} > {} > {}", a, b, c, a);
+    }
+    if let Some(consistence) = transitivity.consistence {
+        println!("Coefficient of consistence: {}", consistence);
+    }
+
+    if weight_matrix.is_complete() && method == PriorityMethod::Mean {
+        let mut normalized_matrix = weight_matrix.values.clone();
+        normalize_weight_matrix(&mut normalized_matrix, sample_set.num);
+        println!("Normalized weight matrix: {}", normalized_matrix);
+    }
+
+    let summary = bootstrap.map(|options| {
+        bootstrap_user(&weight_matrix, sample_set.num, method, options)
+    });
+
+    let criteria_weights = calculate_priorities(weight_matrix, sample_set.num, method)?;
+    println!("Criteria weights ({:?}): {}", method, criteria_weights);
+
+    let mut sample_weights = make_sample_weights(&criteria_weights, &sample_set.ids);
+    if let Some(summary) = summary {
+        apply_bootstrap(&mut sample_weights, &summary, &sample_set.ids);
+    }
+
+    for sample_weight in &sample_weights {
+        print_sample_weight(sample_weight);
+    }
+
+    Ok(())
+}
+
+fn print_sample_weight(sample_weight: &SampleWeight) {
+    match sample_weight.interval {
+        Some((lower, upper)) => println!(
+            "{} <= {} [{}, {}]",
+            sample_weight.name,
+            sample_weight.weight,
+            lower,
+            upper
+        ),
+        None => println!("{} <= {}", sample_weight.name, sample_weight.weight),
+    }
+
+    if let Some(ref rank_probabilities) = sample_weight.rank_probabilities {
+        println!("    Rank probabilities: {:?}", rank_probabilities);
+    }
+}
+
+/// Samples of the task, leaving out the worse samples of gold pairs
+fn get_sample_set(task: &str, storage: &Storage) -> SampleSet {
+    let ids: Vec<ObjectId> = storage
+        .find_samples(task, false)
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    let num = ids.len();
+
+    SampleSet { num: num, ids: ids }
+}
+
+fn get_user_tokens(task: &str, filter: &UserFilter, storage: &Storage) -> Vec<String> {
+    storage
+        .find_users(task)
+        .into_iter()
+        .filter(|user| {
+            filter.source.as_ref().map_or(true, |source| user.source == *source)
+                && filter.gender.as_ref().map_or(true, |gender| {
+                    serde_enum::to_string(&user.gender).unwrap() == *gender
+                })
+                && filter.education.as_ref().map_or(true, |education| {
+                    serde_enum::to_string(&user.education).unwrap() == *education
+                })
+        })
+        .map(|user| user.token)
+        .collect()
+}
+
+/// Load the weights of a user, or of all users if `token` is `None`, as graded comparisons
+fn load_comparisons(
+    token: Option<&str>,
+    metric: &Metric,
+    sample_set: &SampleSet,
+    storage: &Storage,
+) -> Vec<Comparison> {
+    make_comparisons(load_weightings(token, metric, sample_set, storage), sample_set)
+}
+
+/// Comparisons of the weights between samples of the sample set
+fn make_comparisons(weightings: Vec<Weighting>, sample_set: &SampleSet) -> Vec<Comparison> {
+    let indices = make_indices(sample_set);
+
+    weightings
+        .into_iter()
+        .filter_map(|weight| {
+            match (indices.get(&weight.a), indices.get(&weight.b)) {
+                (Some(&a), Some(&b)) => Some(Comparison {
+                    a: a,
+                    b: b,
+                    preference: preference_from_weight(weight.weight),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Score the gold pairs compared by the users of a task, by token. Users that have not compared
+/// any gold pair are left out.
+fn score_gold(
+    task: &str,
+    metric: &Metric,
+    storage: &Storage,
+) -> HashMap<String, GoldScore> {
+    let gold_pairs = storage.find_gold_pairs(task);
+    if gold_pairs.is_empty() {
+        return HashMap::new();
+    }
+    let min_pass_rate = storage.find_manifest(task).gold.min_pass_rate;
+
+    let filter = WeightFilter {
+        metric: Some(metric.clone()),
+        sample_in: Some(gold_pairs.iter().map(|&(_, ref worse)| worse.clone()).collect()),
+        repeat: Some(false),
+        ..Default::default()
+    };
+
+    // A weight above 1 prefers b
+    let mut answers: HashMap<String, Vec<bool>> = HashMap::new();
+    for weighting in find_weightings(&filter, storage) {
+        let pair = (weighting.a.clone(), weighting.b.clone());
+        let passed = if gold_pairs.contains(&pair) {
+            weighting.weight < 1.0
+        } else if gold_pairs.contains(&(pair.1, pair.0)) {
+            weighting.weight > 1.0
+        } else {
+            continue;
+        };
+        answers
+            .entry(weighting.token)
+            .or_insert_with(Vec::new)
+            .push(passed);
+    }
+
+    answers
+        .into_iter()
+        .map(|(token, answers)| (token, make_gold_score(&answers, min_pass_rate)))
+        .collect()
+}
+
+/// Token and public token of each user of the task
+fn get_public_tokens(task: &str, storage: &Storage) -> Vec<(String, String)> {
+    storage
+        .find_users(task)
+        .into_iter()
+        .map(|user| (user.token, user.public))
+        .collect()
+}
+
+/// Load the weights of a user, or of all users if no token is given, between the samples of
+/// the sample set in a single query. Weights that are not positive and repeat measurements are
+/// left out.
+fn load_weightings(
+    token: Option<&str>,
+    metric: &Metric,
+    sample_set: &SampleSet,
+    storage: &Storage,
+) -> Vec<Weighting> {
+    let filter = WeightFilter {
+        token: token.map(|token| token.to_string()),
+        metric: Some(metric.clone()),
+        a_in: Some(sample_set.ids.clone()),
+        repeat: Some(false),
+        ..Default::default()
+    };
+
+    find_weightings(&filter, storage)
+}
+
+/// Load the repeat measurements of all users between the samples of the sample set
+fn load_repeated_weightings(
+    metric: &Metric,
+    sample_set: &SampleSet,
+    storage: &Storage,
+) -> Vec<Weighting> {
+    let filter = WeightFilter {
+        metric: Some(metric.clone()),
+        a_in: Some(sample_set.ids.clone()),
+        repeat: Some(true),
+        ..Default::default()
+    };
+
+    find_weightings(&filter, storage)
+}
+
+fn find_weightings(filter: &WeightFilter, storage: &Storage) -> Vec<Weighting> {
+    storage
+        .find_weightings(filter)
+        .into_iter()
+        .filter(|weight| weight.weight > 0.0)
+        .collect()
+}
+
+fn group_by_user(weightings: Vec<Weighting>) -> HashMap<String, Vec<Weighting>> {
+    let mut users = HashMap::new();
+    for weighting in weightings {
+        users
+            .entry(weighting.token.clone())
+            .or_insert_with(Vec::new)
+            .push(weighting);
+    }
+    users
+}
+
+fn make_indices(sample_set: &SampleSet) -> HashMap<&ObjectId, usize> {
+    sample_set
+        .ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (id, i))
+        .collect()
+}
+
+/// Convert a weight (how many times better `b` is than `a`) to a preference for `b` in [0, 1]
+fn preference_from_weight(weight: f32) -> f64 {
+    0.5 + 0.5 * strength_from_weight(weight)
+}
+
+/// Strength of the preference for `b` from -1 to 1, where 0 means no preference
+fn strength_from_weight(weight: f32) -> f64 {
+    let strength = f64::from(weight).ln() / f64::from(MAX_WEIGHT).ln();
+    f64::min(f64::max(strength, -1.0), 1.0)
+}
+
+fn fit_model(
+    model: Model,
+    comparisons: &[Comparison],
+    sample_set: &SampleSet,
+) -> Result<ModelRanking, Error> {
+    let fit = match choice_model::fit(model, comparisons, sample_set.num) {
+        Some(fit) => fit,
+        None => return Err(Error::Disconnected),
+    };
+
+    let mut scores: Vec<SampleScore> = sample_set
+        .ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            SampleScore {
+                name: id.to_hex(),
+                score: fit.scores[i],
+                standard_error: fit.standard_errors[i],
+            }
+        })
+        .collect();
+    scores.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+
+    Ok(ModelRanking {
+        model: model,
+        scores: scores,
+        log_likelihood: fit.log_likelihood,
+        comparisons: comparisons.len(),
+    })
+}
+
+fn make_weight_matrix(
+    token: &str,
+    metric: &Metric,
+    sample_set: &SampleSet,
+    storage: &Storage,
+) -> WeightMatrix {
+    build_weight_matrix(&load_weightings(Some(token), metric, sample_set, storage), sample_set)
+}
+
+/// Make the weight matrix of each user, loading the weights of all of them in a single query
+fn make_weight_matrices(
+    tokens: &[String],
+    metric: &Metric,
+    sample_set: &SampleSet,
+    storage: &Storage,
+) -> Vec<WeightMatrix> {
+    let weightings = group_by_user(load_weightings(None, metric, sample_set, storage));
+
+    tokens
+        .iter()
+        .map(|token| match weightings.get(token) {
+            Some(weightings) => build_weight_matrix(weightings, sample_set),
+            None => build_weight_matrix(&[], sample_set),
+        })
+        .collect()
+}
+
+/// Build the weight matrix from the weights of a single user.
+///
+/// When a pair has been weighted more than once, the first weight in column major order (`a`
+/// being the sample with the lower index) is used, and otherwise the first in row major order.
+fn build_weight_matrix(weightings: &[Weighting], sample_set: &SampleSet) -> WeightMatrix {
+    let num = sample_set.num;
+    let indices = make_indices(sample_set);
+
+    let mut weight_matrix = DMatrix::<f32>::identity(num, num);
+
+    // Later weights are overwritten by earlier ones, and row major by column major ones
+    for &column_major in &[false, true] {
+        for weighting in weightings.iter().rev() {
+            let (a, b) = match (indices.get(&weighting.a), indices.get(&weighting.b)) {
+                (Some(&a), Some(&b)) if a != b => (a, b),
+                _ => continue,
+            };
+            if (a < b) != column_major {
+                continue;
+            }
+
+            weight_matrix[(b, a)] = weighting.weight;
+            weight_matrix[(a, b)] = 1.0 / weighting.weight;
+        }
+    }
+
+    let mut missing = Vec::new();
+    for col in 0..num {
+        for row in (col + 1)..num {
+            if weight_matrix[(row, col)] == 0.0 {
+                missing.push((col, row));
+            }
+        }
+    }
+
+    WeightMatrix {
+        values: weight_matrix,
+        missing: missing,
+    }
+}
+
+/// Check whether the known comparisons connect all samples
+fn is_connected(weight_matrix: &DMatrix<f32>, num: usize) -> bool {
+    if num == 0 {
+        return true;
+    }
+
+    let mut visited = vec![false; num];
+    let mut queue = VecDeque::new();
+    visited[0] = true;
+    queue.push_back(0);
+
+    while let Some(current) = queue.pop_front() {
+        for next in 0..num {
+            if !visited[next] && weight_matrix[(current, next)] > 0.0 {
+                visited[next] = true;
+                queue.push_back(next);
+            }
+        }
+    }
+
+    visited.into_iter().all(|v| v)
+}
+
+fn normalize_weight_matrix(weight_matrix: &mut DMatrix<f32>, num: usize) {
+    // Normalize on columns
+    for col in 0..num {
+        let sum: f32 = weight_matrix.columns(col, 1).iter().sum();
+        let normalization = 1.0 / sum;
+        for weight in weight_matrix.columns_mut(col, 1).iter_mut() {
+            *weight *= normalization;
+        }
+    }
+}
+
+fn calculate_criteria_weights(weight_matrix: &DMatrix<f32>, num: usize) -> DVector<f32> {
+    // Calculate mean of each row
+    DVector::<f32>::from_iterator(
+        num,
+        (0..num).map(|row| {
+            weight_matrix.rows(row, 1).iter().sum::<f32>() / num as f32
+        }),
+    )
+}
+
+fn calculate_priorities(
+    weight_matrix: WeightMatrix,
+    num: usize,
+    method: PriorityMethod,
+) -> Result<DVector<f32>, Error> {
+    let complete = weight_matrix.is_complete();
+    let mut values = weight_matrix.values;
+
+    if !complete && !is_connected(&values, num) {
+        return Err(Error::Disconnected);
+    }
+
+    let priorities = match method {
+        PriorityMethod::Mean if complete => {
+            normalize_weight_matrix(&mut values, num);
+            calculate_criteria_weights(&values, num)
+        }
+        PriorityMethod::Eigenvector if complete => calculate_eigenvector_weights(&values, num),
+        PriorityMethod::Eigenvector => calculate_harker_weights(&values, num),
+        PriorityMethod::Mean | PriorityMethod::LogLeastSquares => {
+            calculate_log_least_squares_weights(&values, num).ok_or(Error::Disconnected)?
+        }
+    };
+
+    Ok(priorities)
+}
+
+fn calculate_harker_weights(weight_matrix: &DMatrix<f32>, num: usize) -> DVector<f32> {
+    // Harker's method: Missing comparisons are 0 and each diagonal element is one plus the
+    // number of missing comparisons on its row, which keeps the eigenvector equation
+    // consistent with the complete case.
+    let mut harker_matrix = weight_matrix.clone();
+    for row in 0..num {
+        let num_missing = (0..num)
+            .filter(|&col| weight_matrix[(row, col)] == 0.0)
+            .count();
+        harker_matrix[(row, row)] = 1.0 + num_missing as f32;
+    }
+
+    calculate_eigenvector_weights(&harker_matrix, num)
+}
+
+fn calculate_log_least_squares_weights(
+    weight_matrix: &DMatrix<f32>,
+    num: usize,
+) -> Option<DVector<f32>> {
+    // Minimizing the sum of (ln a_ij - (v_i - v_j))^2 over known comparisons gives the
+    // Laplacian system L v = r. Adding a matrix of ones pins the solution to sum(v) = 0, and
+    // makes the system invertible exactly when the comparisons are connected.
+    let mut system = DMatrix::<f64>::from_element(num, num, 1.0);
+    let mut target = DVector::<f64>::from_element(num, 0.0);
+    for row in 0..num {
+        for col in 0..num {
+            let value = weight_matrix[(row, col)];
+            if row != col && value > 0.0 {
+                system[(row, row)] += 1.0;
+                system[(row, col)] -= 1.0;
+                target[row] += f64::from(value).ln();
+            }
+        }
+    }
+
+    let inverse = match system.try_inverse() {
+        Some(inverse) => inverse,
+        None => return None,
+    };
+    let weights: Vec<f64> = (inverse * target).iter().map(|v| v.exp()).collect();
+    let sum: f64 = weights.iter().sum();
+
+    Some(DVector::<f32>::from_iterator(
+        num,
+        weights.into_iter().map(|w| (w / sum) as f32),
+    ))
+}
+
+fn calculate_eigenvector_weights(weight_matrix: &DMatrix<f32>, num: usize) -> DVector<f32> {
+    let (weights, _) = find_principal_eigenvector(weight_matrix, num);
+    DVector::<f32>::from_iterator(num, weights.into_iter())
+}
+
+fn calculate_consistency(weight_matrix: &DMatrix<f32>, num: usize) -> Consistency {
+    let (_, lambda_max) = find_principal_eigenvector(weight_matrix, num);
+
+    if num < 3 {
+        // Matrices of size 1 and 2 are always consistent
+        return Consistency {
+            lambda_max: lambda_max,
+            index: 0.0,
+            ratio: 0.0,
+        };
+    }
+
+    let index = (lambda_max - num as f32) / (num - 1) as f32;
+
+    Consistency {
+        lambda_max: lambda_max,
+        index: index,
+        ratio: index / random_index(num),
+    }
+}
+
+/// Random consistency index for a matrix of size `num`.
+///
+/// Uses Saaty's table when possible, and otherwise the linear fit of the mean random eigenvalue
+/// by Alonso & Lamata (2006): lambda_max = 2.7699n - 4.3513.
+fn random_index(num: usize) -> f32 {
+    if num <= RANDOM_INDEX.len() {
+        RANDOM_INDEX[num - 1]
+    } else {
+        let n = num as f32;
+        (1.7699 * n - 4.3513) / (n - 1.0)
+    }
+}
+
+/// Find the principal eigenvector (normalized to sum to 1) and eigenvalue of a positive matrix.
+fn find_principal_eigenvector(weight_matrix: &DMatrix<f32>, num: usize) -> (Vec<f32>, f32) {
+    // Power iteration: A positive reciprocal matrix has a unique positive principal eigenvector
+    // (Perron-Frobenius), so repeatedly multiplying and normalizing any positive start vector
+    // converges to it.
+    let mut weights = vec![1.0 / num as f32; num];
+    let mut lambda_max = num as f32;
+
+    for _ in 0..EIGENVECTOR_MAX_ITERATIONS {
+        let mut next: Vec<f32> = (0..num)
+            .map(|row| {
+                (0..num)
+                    .map(|col| weight_matrix[(row, col)] * weights[col])
+                    .sum()
+            })
+            .collect();
+
+        // Since the previous vector sums to 1, the sum of the product converges to the eigenvalue
+        let sum: f32 = next.iter().sum();
+        lambda_max = sum;
+        for weight in &mut next {
+            *weight /= sum;
+        }
+
+        let change = next.iter()
+            .zip(weights.iter())
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        weights = next;
+
+        if change < EIGENVECTOR_TOLERANCE {
+            break;
+        }
+    }
+
+    (weights, lambda_max)
+}
+
+/// Rank the samples by the weights of a single user, with the consistency of a complete matrix
+/// and the pairs that are missing
+fn rank_weight_matrix(
+    weight_matrix: WeightMatrix,
+    sample_set: &SampleSet,
+    method: PriorityMethod,
+    bootstrap: Option<&bootstrap::Options>,
+) -> Result<Ranking, Error> {
+    let num = sample_set.num;
+    let consistency = if weight_matrix.is_complete() {
+        Some(calculate_consistency(&weight_matrix.values, num))
+    } else {
+        None
+    };
+    let missing = make_missing_pairs(&weight_matrix.missing, &sample_set.ids);
+
+    let summary = bootstrap.map(|options| bootstrap_user(&weight_matrix, num, method, options));
+
+    let criteria_weights = calculate_priorities(weight_matrix, num, method)?;
+    let mut weights = make_sample_weights(&criteria_weights, &sample_set.ids);
+    if let Some(summary) = summary {
+        apply_bootstrap(&mut weights, &summary, &sample_set.ids);
+    }
+
+    Ok(Ranking {
+        weights: weights,
+        consistency: consistency,
+        missing: missing,
+    })
+}
+
+/// Consistency of a weight matrix, only given once all pairs have been weighed
+fn complete_consistency(weight_matrix: &WeightMatrix, num: usize) -> Result<Consistency, Error> {
+    if !weight_matrix.is_complete() {
+        return Err(Error::MissingWeights);
+    }
+
+    Ok(calculate_consistency(&weight_matrix.values, num))
+}
+
+/// Find the circular triads of a user and, if all pairs were weighed, their consistence
+fn make_transitivity(weight_matrix: &WeightMatrix, sample_set: &SampleSet) -> Transitivity {
+    let triads = find_circular_triads(&weight_matrix.values, sample_set.num);
+    let consistence = if weight_matrix.is_complete() {
+        Some(coefficient_of_consistence(triads.len(), sample_set.num))
+    } else {
+        None
+    };
+    let ids = &sample_set.ids;
+
+    Transitivity {
+        circular_triads: triads.len(),
+        consistence: consistence,
+        triads: triads
+            .into_iter()
+            .map(|(a, b, c)| (ids[a].to_hex(), ids[b].to_hex(), ids[c].to_hex()))
+            .collect(),
+    }
+}
+
+/// Find all triples where each sample is strictly preferred over the next and the last over
+/// the first. Ties and missing comparisons never form a circular triad.
+fn find_circular_triads(weight_matrix: &DMatrix<f32>, num: usize) -> Vec<(usize, usize, usize)> {
+    // Row sample is preferred over column sample
+    let prefers = |row: usize, col: usize| weight_matrix[(row, col)] > 1.0;
+
+    let mut triads = Vec::new();
+    for i in 0..num {
+        for j in (i + 1)..num {
+            for k in (j + 1)..num {
+                if prefers(i, j) && prefers(j, k) && prefers(k, i) {
+                    triads.push((i, j, k));
+                } else if prefers(i, k) && prefers(k, j) && prefers(j, i) {
+                    triads.push((i, k, j));
+                }
+            }
+        }
+    }
+
+    triads
+}
+
+/// Kendall's coefficient of consistence: 1 minus the number of circular triads divided by the
+/// largest possible number for `num` samples
+fn coefficient_of_consistence(circular_triads: usize, num: usize) -> f32 {
+    let n = num as f32;
+    let max_triads = if num % 2 == 1 {
+        (n * n * n - n) / 24.0
+    } else {
+        (n * n * n - 4.0 * n) / 24.0
+    };
+
+    if max_triads > 0.0 {
+        1.0 - circular_triads as f32 / max_triads
+    } else {
+        1.0
+    }
+}
+
+/// Side bias of weights, where the sample on the left is `a`, which a weight below 1 prefers
+fn make_side_bias(weights: &[f32]) -> SideBias {
+    let left = weights.iter().filter(|&&weight| weight < 1.0).count();
+    let right = weights.iter().filter(|&&weight| weight > 1.0).count();
+
+    let mean_strength = if weights.is_empty() {
+        0.0
+    } else {
+        weights
+            .iter()
+            .map(|&weight| strength_from_weight(weight))
+            .sum::<f64>() / weights.len() as f64
+    };
+
+    SideBias {
+        comparisons: weights.len(),
+        left: left,
+        right: right,
+        mean_strength: mean_strength,
+        p_value: distribution::binomial_two_sided_p(right, left + right),
+    }
+}
+
+/// Reliability from the strengths of the first and second preference of each repeated pair
+fn make_reliability(strengths: &[(f64, f64)]) -> Reliability {
+    if strengths.is_empty() {
+        return Reliability {
+            repeated: 0,
+            agreement: None,
+            correlation: None,
+            mean_absolute_difference: None,
+        };
+    }
+
+    fn direction(strength: f64) -> i8 {
+        if strength > 0.0 {
+            1
+        } else if strength < 0.0 {
+            -1
+        } else {
+            0
+        }
+    }
+
+    let repeated = strengths.len();
+    let agreeing = strengths
+        .iter()
+        .filter(|&&(first, second)| direction(first) == direction(second))
+        .count();
+    let mean_absolute_difference = strengths
+        .iter()
+        .map(|&(first, second)| (first - second).abs())
+        .sum::<f64>() / repeated as f64;
+
+    let correlation = if repeated >= 3 {
+        let (first, second): (Vec<f64>, Vec<f64>) = strengths.iter().cloned().unzip();
+        correlation::pearson(&first, &second)
+    } else {
+        None
+    };
+
+    Reliability {
+        repeated: repeated,
+        agreement: Some(agreeing as f64 / repeated as f64),
+        correlation: correlation,
+        mean_absolute_difference: Some(mean_absolute_difference),
+    }
+}
+
+fn make_gold_score(answers: &[bool], min_pass_rate: f64) -> GoldScore {
+    let checks = answers.len();
+    let passed = answers.iter().filter(|&&passed| passed).count();
+    let pass_rate = if checks > 0 {
+        Some(passed as f64 / checks as f64)
+    } else {
+        None
+    };
+
+    GoldScore {
+        checks: checks,
+        passed: passed,
+        pass_rate: pass_rate,
+        failed: pass_rate.map_or(false, |pass_rate| pass_rate < min_pass_rate),
+    }
+}
+
+fn make_agreement(
+    group: Option<String>,
+    weight_matrices: Vec<WeightMatrix>,
+    num: usize,
+) -> Agreement {
+    let num_pairs = num * num.saturating_sub(1) / 2;
+    let weight_matrices: Vec<WeightMatrix> = weight_matrices
+        .into_iter()
+        .filter(|weight_matrix| weight_matrix.missing.len() < num_pairs)
+        .collect();
+    let users = weight_matrices.len();
+
+    let mut votes = vec![PairVotes::default(); num_pairs];
+    for weight_matrix in &weight_matrices {
+        let mut pair = 0;
+        for col in 0..num {
+            for row in (col + 1)..num {
+                let value = weight_matrix.values[(row, col)];
+                if value > 1.0 {
+                    votes[pair].b += 1.0;
+                } else if value == 1.0 {
+                    votes[pair].ties += 1.0;
+                } else if value > 0.0 {
+                    votes[pair].a += 1.0;
+                }
+                pair += 1;
+            }
+        }
+    }
+
+    let scores: Vec<Vec<f64>> = weight_matrices
+        .into_iter()
+        .filter_map(|weight_matrix| {
+            calculate_priorities(weight_matrix, num, PriorityMethod::default())
+                .ok()
+                .map(|priorities| priorities.iter().map(|&p| f64::from(p)).collect())
+        })
+        .collect();
+
+    Agreement {
+        group: group,
+        users: users,
+        ranked_users: scores.len(),
+        concordance: agreement::kendall_w(&scores),
+        kendall_u: agreement::kendall_u(&votes),
+    }
+}
+
+/// Bootstrap the priorities of a single user, who can only be resampled by their comparisons
+fn bootstrap_user(
+    weight_matrix: &WeightMatrix,
+    num: usize,
+    method: PriorityMethod,
+    options: &bootstrap::Options,
+) -> bootstrap::Summary {
+    let judgments = find_judgments(&weight_matrix.values, num);
+    let estimates = bootstrap::run(options, |rng| {
+        let resampled = resample_judgments(&judgments, rng);
+        calculate_priorities(make_judgment_matrix(&resampled, num), num, method)
+            .ok()
+            .map(|priorities| priorities.iter().cloned().collect())
+    });
+
+    bootstrap::summarize(&estimates, num, options.level)
+}
+
+/// Find all known judgments in the lower triangle of a weight matrix
+fn find_judgments(weight_matrix: &DMatrix<f32>, num: usize) -> Vec<Judgment> {
+    let mut judgments = Vec::new();
+    for col in 0..num {
+        for row in (col + 1)..num {
+            let value = weight_matrix[(row, col)];
+            if value > 0.0 {
+                judgments.push(Judgment {
+                    col: col,
+                    row: row,
+                    value: value,
+                });
+            }
+        }
+    }
+
+    judgments
+}
+
+/// Draw as many judgments as there are with replacement
+fn resample_judgments<R: Rng>(judgments: &[Judgment], rng: &mut R) -> Vec<Judgment> {
+    bootstrap::resample(rng, judgments.len())
+        .into_iter()
+        .map(|i| judgments[i])
+        .collect()
+}
+
+/// Make a weight matrix from judgments, where a pair that has been judged several times gets
+/// the geometric mean of its judgments.
+fn make_judgment_matrix(judgments: &[Judgment], num: usize) -> WeightMatrix {
+    let mut log_sums = DMatrix::<f32>::from_element(num, num, 0.0);
+    let mut counts = DMatrix::<f32>::from_element(num, num, 0.0);
+    for judgment in judgments {
+        log_sums[(judgment.row, judgment.col)] += judgment.value.ln();
+        counts[(judgment.row, judgment.col)] += 1.0;
+    }
+
+    let mut values = DMatrix::<f32>::identity(num, num);
+    let mut missing = Vec::new();
+    for col in 0..num {
+        for row in (col + 1)..num {
+            let count = counts[(row, col)];
+            if count > 0.0 {
+                let log_mean = log_sums[(row, col)] / count;
+                values[(row, col)] = log_mean.exp();
+                values[(col, row)] = (-log_mean).exp();
+            } else {
+                values[(row, col)] = 0.0;
+                values[(col, row)] = 0.0;
+                missing.push((col, row));
+            }
+        }
+    }
+
+    WeightMatrix {
+        values: values,
+        missing: missing,
+    }
+}
+
+/// Combine the judgments of several users into group priorities.
+///
+/// With AIJ, a pair is only missing when no user has compared it. With AIP, users whose
+/// comparisons do not connect all samples have no priorities and are left out.
+fn aggregate(
+    user_judgments: &[Vec<Judgment>],
+    num: usize,
+    method: PriorityMethod,
+    aggregation: Aggregation,
+) -> Result<Aggregate, Error> {
+    match aggregation {
+        Aggregation::Aij => {
+            let judgments: Vec<Judgment> = user_judgments
+                .iter()
+                .flat_map(|judgments| judgments.iter().cloned())
+                .collect();
+            let weight_matrix = make_judgment_matrix(&judgments, num);
+            let consistency = if weight_matrix.is_complete() {
+                Some(calculate_consistency(&weight_matrix.values, num))
+            } else {
+                None
+            };
+            let missing = weight_matrix.missing.clone();
+
+            Ok(Aggregate {
+                priorities: calculate_priorities(weight_matrix, num, method)?,
+                consistency: consistency,
+                missing: missing,
+                users: user_judgments.len(),
+            })
+        }
+        Aggregation::Aip => {
+            let priorities: Vec<DVector<f32>> = user_judgments
+                .iter()
+                .filter_map(|judgments| {
+                    calculate_priorities(make_judgment_matrix(judgments, num), num, method).ok()
+                })
+                .collect();
+
+            if priorities.is_empty() {
+                return Err(Error::Disconnected);
+            }
+
+            Ok(Aggregate {
+                priorities: aggregate_priorities(&priorities, num),
+                consistency: None,
+                missing: Vec::new(),
+                users: priorities.len(),
+            })
+        }
+    }
+}
+
+/// Combine the priorities of several users through their normalized geometric mean
+fn aggregate_priorities(priorities: &[DVector<f32>], num: usize) -> DVector<f32> {
+    let means: Vec<f32> = (0..num)
+        .map(|i| {
+            let log_sum: f32 = priorities.iter().map(|p| p[i].ln()).sum();
+            (log_sum / priorities.len() as f32).exp()
+        })
+        .collect();
+    let sum: f32 = means.iter().sum();
+
+    DVector::<f32>::from_iterator(num, means.into_iter().map(|mean| mean / sum))
+}
+
+fn make_missing_pairs(missing: &[(usize, usize)], ids: &[ObjectId]) -> Vec<(String, String)> {
+    missing
+        .iter()
+        .map(|&(a, b)| (ids[a].to_hex(), ids[b].to_hex()))
+        .collect()
+}
+
+fn make_sample_weights(criteria_weights: &DVector<f32>, ids: &[ObjectId]) -> Vec<SampleWeight> {
+    let mut sample_weights: Vec<_> = criteria_weights
+        .iter()
+        .enumerate()
+        .map(|(i, w)| {
+            SampleWeight {
+                name: ids[i].to_hex(),
+                weight: *w,
+                interval: None,
+                rank_probabilities: None,
+            }
+        })
+        .collect();
+    sample_weights.sort_by(|a, b| b.weight.partial_cmp(&a.weight).unwrap());
+    sample_weights
+}
+
+/// Add the bootstrap intervals and rank probabilities to the sample weights
+fn apply_bootstrap(
+    sample_weights: &mut [SampleWeight],
+    summary: &bootstrap::Summary,
+    ids: &[ObjectId],
+) {
+    if summary.replicates == 0 {
+        return;
+    }
+
+    for sample_weight in sample_weights {
+        if let Some(i) = ids.iter().position(|id| id.to_hex() == sample_weight.name) {
+            sample_weight.interval = Some(summary.intervals[i]);
+            sample_weight.rank_probabilities = Some(summary.rank_probabilities[i].clone());
+        }
+    }
+}
+
+#[test]
+fn test_eigenvector_weights() {
+    // Perfectly consistent matrix where w = [4, 2, 1] / 7
+    let weight_matrix =
+        DMatrix::<f32>::from_row_slice(3, 3, &[1.0, 2.0, 4.0, 0.5, 1.0, 2.0, 0.25, 0.5, 1.0]);
+    let weights = calculate_eigenvector_weights(&weight_matrix, 3);
+
+    let expected = [4.0 / 7.0, 2.0 / 7.0, 1.0 / 7.0];
+    for (weight, expected) in weights.iter().zip(expected.iter()) {
+        assert!((weight - expected).abs() < 1.0e-5);
+    }
+}
+
+#[test]
+fn test_consistency() {
+    // Perfectly consistent matrix has lambda_max = n
+    let weight_matrix =
+        DMatrix::<f32>::from_row_slice(3, 3, &[1.0, 2.0, 4.0, 0.5, 1.0, 2.0, 0.25, 0.5, 1.0]);
+    let consistency = calculate_consistency(&weight_matrix, 3);
+    assert!((consistency.lambda_max - 3.0).abs() < 1.0e-4);
+    assert!(consistency.ratio.abs() < 1.0e-3);
+
+    // Circular judgments (A > B > C > A) are highly inconsistent
+    let weight_matrix = DMatrix::<f32>::from_row_slice(
+        3,
+        3,
+        &[1.0, 5.0, 1.0 / 5.0, 1.0 / 5.0, 1.0, 5.0, 5.0, 1.0 / 5.0, 1.0],
+    );
+    let consistency = calculate_consistency(&weight_matrix, 3);
+    assert!(consistency.lambda_max > 3.0);
+    assert!(consistency.ratio > 0.1);
+}
+
+#[test]
+fn test_incomplete_weights() {
+    // Consistent matrix where w = [4, 2, 1] / 7 with the comparison of 1 and 3 missing
+    let weight_matrix =
+        DMatrix::<f32>::from_row_slice(3, 3, &[1.0, 2.0, 0.0, 0.5, 1.0, 2.0, 0.0, 0.5, 1.0]);
+    assert!(is_connected(&weight_matrix, 3));
+
+    let expected = [4.0 / 7.0, 2.0 / 7.0, 1.0 / 7.0];
+
+    let weights = calculate_log_least_squares_weights(&weight_matrix, 3).unwrap();
+    for (weight, expected) in weights.iter().zip(expected.iter()) {
+        assert!((weight - expected).abs() < 1.0e-5);
+    }
+
+    let weights = calculate_harker_weights(&weight_matrix, 3);
+    for (weight, expected) in weights.iter().zip(expected.iter()) {
+        assert!((weight - expected).abs() < 1.0e-5);
+    }
+
+    // Sample 3 has no comparisons at all
+    let weight_matrix =
+        DMatrix::<f32>::from_row_slice(3, 3, &[1.0, 2.0, 0.0, 0.5, 1.0, 0.0, 0.0, 0.0, 1.0]);
+    assert!(!is_connected(&weight_matrix, 3));
+}
+
+#[test]
+fn test_aggregation() {
+    // Two users that disagree by a factor of 4 on the first pair, which the second user has
+    // not compared against the third sample
+    let weight_matrices = [
+        WeightMatrix {
+            values: DMatrix::<f32>::from_row_slice(
+                3,
+                3,
+                &[1.0, 1.0, 2.0, 1.0, 1.0, 2.0, 0.5, 0.5, 1.0],
+            ),
+            missing: Vec::new(),
+        },
+        WeightMatrix {
+            values: DMatrix::<f32>::from_row_slice(
+                3,
+                3,
+                &[1.0, 4.0, 0.0, 0.25, 1.0, 2.0, 0.0, 0.5, 1.0],
+            ),
+            missing: vec![(0, 2)],
+        },
+    ];
+
+    let judgments: Vec<Judgment> = weight_matrices
+        .iter()
+        .flat_map(|weight_matrix| find_judgments(&weight_matrix.values, 3))
+        .collect();
+    let aggregated = make_judgment_matrix(&judgments, 3);
+    assert!(aggregated.is_complete());
+    assert!((aggregated.values[(0, 1)] - 2.0).abs() < 1.0e-5);
+    assert!((aggregated.values[(1, 0)] - 0.5).abs() < 1.0e-5);
+    assert!((aggregated.values[(0, 2)] - 2.0).abs() < 1.0e-5);
+
+    let priorities = [
+        DVector::<f32>::from_iterator(2, vec![0.8, 0.2].into_iter()),
+        DVector::<f32>::from_iterator(2, vec![0.2, 0.8].into_iter()),
+    ];
+    let aggregated = aggregate_priorities(&priorities, 2);
+    assert!((aggregated[0] - 0.5).abs() < 1.0e-5);
+}
+
+#[test]
+fn test_circular_triads() {
+    // 0 > 1, 1 > 2 and 2 > 0, while 3 loses to everything
+    let weight_matrix = DMatrix::<f32>::from_row_slice(
+        4,
+        4,
+        &[
+            1.0, 3.0, 1.0 / 3.0, 5.0,
+            1.0 / 3.0, 1.0, 3.0, 5.0,
+            3.0, 1.0 / 3.0, 1.0, 5.0,
+            0.2, 0.2, 0.2, 1.0,
+        ],
+    );
+
+    assert_eq!(find_circular_triads(&weight_matrix, 4), vec![(0, 1, 2)]);
+    // At most 2 circular triads are possible among 4 samples
+    assert!((coefficient_of_consistence(1, 4) - 0.5).abs() < 1.0e-6);
+    assert!((coefficient_of_consistence(0, 5) - 1.0).abs() < 1.0e-6);
+}
+
+#[test]
+fn test_side_bias() {
+    let bias = make_side_bias(&[9.0, 9.0, 1.0, 1.0 / 9.0]);
+    assert_eq!((bias.comparisons, bias.left, bias.right), (4, 1, 2));
+    assert!((bias.mean_strength - 0.25).abs() < 1.0e-6);
+    assert!((bias.p_value - 1.0).abs() < 1.0e-9);
+
+    let bias = make_side_bias(&[]);
+    assert_eq!(bias.comparisons, 0);
+    assert!((bias.p_value - 1.0).abs() < 1.0e-9);
+}
+
+#[test]
+fn test_reliability() {
+    let reliability = make_reliability(&[(1.0, 1.0), (-0.5, -1.0), (0.5, 0.0), (0.0, 0.0)]);
+    assert_eq!(reliability.repeated, 4);
+    assert!((reliability.agreement.unwrap() - 0.75).abs() < 1.0e-9);
+    assert!((reliability.mean_absolute_difference.unwrap() - 0.25).abs() < 1.0e-9);
+    assert!(reliability.correlation.unwrap() > 0.8);
+
+    assert!(make_reliability(&[]).agreement.is_none());
+    assert!(make_reliability(&[(1.0, 1.0), (-1.0, -1.0)]).correlation.is_none());
+}
+
+#[test]
+fn test_gold_score() {
+    let score = make_gold_score(&[true, true, false, true], 0.8);
+    assert_eq!((score.checks, score.passed), (4, 3));
+    assert_eq!(score.pass_rate, Some(0.75));
+    assert!(score.failed);
+
+    assert!(!make_gold_score(&[true, true, false, true], 0.75).failed);
+    assert!(!make_gold_score(&[], 1.0).failed);
+}
+
+#[test]
+fn test_sample_weights_from_storage() {
+    use memory::{save_test_samples, test_weighting, MemoryStorage};
+
+    let storage = MemoryStorage::new();
+    let ids = save_test_samples(&storage, "task", &["first", "second", "third"]);
+
+    // Each sample is strongly preferred over the samples after it
+    let metric = Metric::new("pleasing").unwrap();
+    for &(a, b) in &[(0, 1), (1, 2), (0, 2)] {
+        let weighting = test_weighting("user", &ids[a], &ids[b], 1.0 / MAX_WEIGHT);
+        storage.insert_weighting(&weighting).unwrap();
+    }
+
+    let ranking = calculate_sample_weights(
+        "task",
+        "user",
+        &metric,
+        PriorityMethod::default(),
+        None,
+        &storage,
+    ).unwrap();
+    let names: Vec<String> = ranking.weights.into_iter().map(|weight| weight.name).collect();
+    let expected: Vec<String> = ids.iter().map(|id| id.to_hex()).collect();
+    assert_eq!(names, expected);
+    assert!(ranking.missing.is_empty());
+
+    assert!(calculate_sample_weights(
+        "task",
+        "other",
+        &metric,
+        PriorityMethod::default(),
+        None,
+        &storage,
+    ).is_err());
 }
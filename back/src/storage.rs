@@ -0,0 +1,282 @@
+use bson::oid::ObjectId;
+use std::collections::HashMap;
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::process;
+
+use cfg::{self, Backend};
+use db::{self, Presentation, Task, User, Weighting};
+use manifest::Manifest;
+use memory::MemoryStorage;
+use model::{Metric, Sample};
+use sqlite::SqliteStorage;
+
+/// Restricts which weights are found, where every condition that is set must hold
+#[derive(Clone, Debug, Default)]
+pub struct WeightFilter {
+    pub token: Option<String>,
+    /// Only weights of one of these users
+    pub tokens: Option<Vec<String>>,
+    pub metric: Option<Metric>,
+    /// Only weights where `a` is one of these samples
+    pub a_in: Option<Vec<ObjectId>>,
+    /// Only weights where `a` or `b` is one of these samples
+    pub sample_in: Option<Vec<ObjectId>>,
+    /// Only weights of this pair, with `a` and `b` in this order
+    pub pair: Option<(ObjectId, ObjectId)>,
+    /// Only repeat measurements, or only first measurements
+    pub repeat: Option<bool>,
+}
+
+impl WeightFilter {
+    /// Whether the weight passes the filter, for backends that do not filter in their queries
+    pub fn matches(&self, weighting: &Weighting) -> bool {
+        if let Some(ref token) = self.token {
+            if weighting.token != *token {
+                return false;
+            }
+        }
+        if let Some(ref tokens) = self.tokens {
+            if !tokens.contains(&weighting.token) {
+                return false;
+            }
+        }
+        if let Some(ref metric) = self.metric {
+            if weighting.metric != *metric {
+                return false;
+            }
+        }
+        if let Some(ref a_in) = self.a_in {
+            if !a_in.contains(&weighting.a) {
+                return false;
+            }
+        }
+        if let Some(ref sample_in) = self.sample_in {
+            if !sample_in.contains(&weighting.a) && !sample_in.contains(&weighting.b) {
+                return false;
+            }
+        }
+        if let Some((ref a, ref b)) = self.pair {
+            if weighting.a != *a || weighting.b != *b {
+                return false;
+            }
+        }
+        if let Some(repeat) = self.repeat {
+            if weighting.repeat != repeat {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// A record with the same key has already been stored
+    Duplicate,
+    Backend(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Error::Duplicate => f.write_str("record already stored"),
+            Error::Backend(ref message) => write!(f, "storage failed: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Duplicate => "record already stored",
+            Error::Backend(ref message) => message.as_str(),
+        }
+    }
+}
+
+/// Samples, tasks, users, weights and presentations of the studies.
+///
+/// Reads that fail panic, like a failed query did before storage was abstracted, while writes
+/// report their errors so that duplicates can be told apart.
+pub trait Storage: Send + Sync {
+    /// Prepare the storage for use, such as by creating indexes
+    fn init(&self) -> Result<(), Error>;
+
+    /// Samples of a task with their IDs, in the order they were registered. The worse samples
+    /// of gold pairs are only included if `include_gold` is set.
+    fn find_samples(&self, task: &str, include_gold: bool) -> Vec<(ObjectId, Sample)>;
+    fn find_sample(&self, id: &ObjectId) -> Option<Sample>;
+    /// Names of the tasks that have samples
+    fn find_task_names(&self) -> Vec<String>;
+    /// Register a sample, replacing the sample with the same task and name
+    fn save_sample(&self, sample: &Sample) -> Result<(), Error>;
+
+    /// Get a task, or `None` if it has not been registered
+    fn find_task(&self, name: &str) -> Option<Task>;
+    /// Register a task, replacing the task with the same name
+    fn save_task(&self, task: &Task) -> Result<(), Error>;
+
+    fn find_user(&self, token: &str) -> Option<User>;
+    /// Users of a task, in the order they registered
+    fn find_users(&self, task: &str) -> Vec<User>;
+    fn insert_user(&self, user: &User) -> Result<(), Error>;
+    /// Replace the user with the same token, returning whether there was one
+    fn update_user(&self, user: &User) -> Result<bool, Error>;
+
+    /// Weights that pass the filter, in the order they were stored
+    fn find_weightings(&self, filter: &WeightFilter) -> Vec<Weighting>;
+    /// Store a weight, failing with `Error::Duplicate` if the user has already weighted the
+    /// pair by the metric
+    fn insert_weighting(&self, weighting: &Weighting) -> Result<(), Error>;
+    /// Replace the weight of the same user, metric, pair and repeat, returning whether there
+    /// was one
+    fn update_weighting(&self, weighting: &Weighting) -> Result<bool, Error>;
+
+    /// Pairs that have been presented to a user
+    fn find_user_presentations(&self, token: &str) -> Vec<Presentation>;
+    /// How often each sample of a task, by its hex ID, has been presented on the left minus
+    /// how often on the right, to all users of the task
+    fn find_side_imbalance(&self, task: &str) -> HashMap<String, i32>;
+    /// Record a presentation and count the sides of its samples, unless the pair has already
+    /// been presented to the user with the same sides
+    fn record_presentation(&self, presentation: &Presentation) -> Result<(), Error>;
+
+    /// Get the manifest of a task, or the defaults if the task has not been registered
+    fn find_manifest(&self, task: &str) -> Manifest {
+        match self.find_task(task) {
+            Some(task) => task.manifest,
+            None => Manifest::default(),
+        }
+    }
+
+    /// Get the better and worse sample of each gold pair of a task, leaving out pairs with
+    /// samples that have not been registered
+    fn find_gold_pairs(&self, task: &str) -> Vec<(ObjectId, ObjectId)> {
+        let gold_pairs = self.find_manifest(task).gold.pairs;
+        if gold_pairs.is_empty() {
+            return Vec::new();
+        }
+
+        let ids: HashMap<String, ObjectId> = self.find_samples(task, true)
+            .into_iter()
+            .map(|(id, sample)| (sample.name, id))
+            .collect();
+
+        gold_pairs
+            .iter()
+            .filter_map(|pair| match (ids.get(&pair.better), ids.get(&pair.worse)) {
+                (Some(better), Some(worse)) => Some((better.clone(), worse.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Open the storage given by the configuration, exiting if it cannot be opened
+pub fn open(cfg: &cfg::Db) -> Box<Storage> {
+    match try_open(cfg) {
+        Ok(storage) => storage,
+        Err(error) => {
+            println!("Failed to open DB: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+fn try_open(cfg: &cfg::Db) -> Result<Box<Storage>, Error> {
+    Ok(match cfg.backend {
+        Backend::Mongodb => Box::new(db::MongoStorage::connect(cfg)?),
+        // The commands read a new file as soon as it is opened, so its tables are created
+        // right away
+        Backend::Sqlite => {
+            let storage = SqliteStorage::open(&cfg.path)?;
+            storage.init()?;
+            Box::new(storage)
+        }
+        Backend::Memory => {
+            println!("Storing in memory, which is lost when the server stops");
+            Box::new(MemoryStorage::new())
+        }
+    })
+}
+
+/// Check the operations of a backend that has been initialized and is still empty, for the
+/// tests of each backend
+#[cfg(test)]
+pub fn check_storage(storage: &Storage) {
+    use chrono::Utc;
+    use memory::test_weighting;
+
+    for &(name, fitness) in &[("a", 1.0), ("b", 2.0), ("a", 3.0)] {
+        let sample = Sample {
+            task: "task".to_string(),
+            name: name.to_string(),
+            fitness: fitness,
+            gold: name == "b",
+        };
+        storage.save_sample(&sample).unwrap();
+    }
+    assert_eq!(storage.find_samples("task", false).len(), 1);
+    let samples = storage.find_samples("task", true);
+    assert_eq!(samples.len(), 2);
+    assert!((samples[0].1.fitness - 3.0).abs() < 1.0e-6);
+    assert_eq!(storage.find_sample(&samples[1].0).unwrap().name, "b");
+    assert_eq!(storage.find_task_names(), vec!["task".to_string()]);
+
+    let (a, b) = (samples[0].0.clone(), samples[1].0.clone());
+    let weighting = |repeat: bool| Weighting {
+        repeat: repeat,
+        ..test_weighting("user", &a, &b, 9.0)
+    };
+
+    storage.insert_weighting(&weighting(false)).unwrap();
+    match storage.insert_weighting(&weighting(false)) {
+        Err(Error::Duplicate) => {}
+        _ => panic!("Duplicate weight was stored"),
+    }
+    storage.insert_weighting(&weighting(true)).unwrap();
+
+    let filter = WeightFilter {
+        token: Some("user".to_string()),
+        pair: Some((a.clone(), b.clone())),
+        repeat: Some(false),
+        ..Default::default()
+    };
+    let found = storage.find_weightings(&filter);
+    assert_eq!(found.len(), 1);
+    assert_eq!((&found[0].a, &found[0].b), (&a, &b));
+
+    let mut updated = weighting(true);
+    updated.weight = 3.0;
+    assert!(storage.update_weighting(&updated).unwrap());
+    let found = storage.find_weightings(&WeightFilter {
+        repeat: Some(true),
+        ..Default::default()
+    });
+    assert!((found[0].weight - 3.0).abs() < 1.0e-6);
+
+    let filter = WeightFilter {
+        sample_in: Some(vec![a.clone()]),
+        ..Default::default()
+    };
+    assert_eq!(storage.find_weightings(&filter).len(), 2);
+
+    let presentation = |token: &str| Presentation {
+        token: token.to_string(),
+        task: "task".to_string(),
+        a: a.clone(),
+        b: b.clone(),
+        position: 0,
+        repeat: false,
+        time: Utc::now().naive_utc(),
+    };
+    storage.record_presentation(&presentation("user")).unwrap();
+    storage.record_presentation(&presentation("user")).unwrap();
+    storage.record_presentation(&presentation("other")).unwrap();
+    assert_eq!(storage.find_user_presentations("user").len(), 1);
+    let imbalance = storage.find_side_imbalance("task");
+    assert_eq!(imbalance.get(&a.to_hex()), Some(&2));
+    assert_eq!(imbalance.get(&b.to_hex()), Some(&-2));
+}